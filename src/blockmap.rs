@@ -0,0 +1,511 @@
+use crate::wad::{LineDef, Vertex, WadError};
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use std::collections::HashMap;
+
+// Every block in the blockmap is a 128 by 128 square
+pub const BLOCK_SIZE: f64 = 128.0;
+
+// The blockmap splits the map into a grid and lists which linedefs go through each block
+// so collision and hitscans only have to check the lines that are nearby
+pub struct Blockmap {
+    // Bottom left corner of the grid
+    pub x_origin: i16,
+    pub y_origin: i16,
+
+    // Size of the grid in blocks
+    pub columns: usize,
+    pub rows: usize,
+
+    // Linedef indexes in each block, going left to right then bottom to top
+    cells: Vec<Vec<usize>>,
+}
+
+impl Blockmap {
+    // Reads the BLOCKMAP lump, maps without one just don't have a blockmap
+    pub fn from_bytes(data: &[u8]) -> Result<Option<Blockmap>, WadError> {
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        if data.len() < 8 {
+            return Err(WadError::MalformedLump {
+                name: "BLOCKMAP".to_string(),
+                offset: 0,
+                reason: "too small to have a header".to_string(),
+            });
+        }
+
+        let x_origin = <LittleEndian as ByteOrder>::read_i16(&data[0..2]);
+        let y_origin = <LittleEndian as ByteOrder>::read_i16(&data[2..4]);
+        let columns = <LittleEndian as ByteOrder>::read_u16(&data[4..6]) as usize;
+        let rows = <LittleEndian as ByteOrder>::read_u16(&data[6..8]) as usize;
+
+        // Everything in the blockmap is 16 bit so it's easier to work with words than bytes
+        let num_of_words = data.len() / 2;
+        let word = |i: usize| <LittleEndian as ByteOrder>::read_u16(&data[i * 2..i * 2 + 2]);
+
+        if 4 + columns * rows > num_of_words {
+            return Err(WadError::MalformedLump {
+                name: "BLOCKMAP".to_string(),
+                offset: 8,
+                reason: format!("offsets for {} by {} blocks don't fit", columns, rows),
+            });
+        }
+
+        let mut cells: Vec<Vec<usize>> = Vec::new();
+        for i in 0..columns * rows {
+            // Offset of the block's list of linedefs in words from the start of the lump
+            let mut list_loc = word(4 + i) as usize;
+
+            // Every list starts with a 0, Boom skips it but vanilla checks linedef 0 in every block because of it
+            if list_loc < num_of_words && word(list_loc) == 0 {
+                list_loc += 1;
+            }
+
+            // The list ends with -1
+            let mut linedefs: Vec<usize> = Vec::new();
+            loop {
+                if list_loc >= num_of_words {
+                    return Err(WadError::MalformedLump {
+                        name: "BLOCKMAP".to_string(),
+                        offset: (4 + i) * 2,
+                        reason: format!("list for block {} runs past the end of the lump", i),
+                    });
+                }
+
+                let linedef = word(list_loc);
+                if linedef == 0xFFFF {
+                    break;
+                }
+                linedefs.push(linedef as usize);
+                list_loc += 1;
+            }
+
+            cells.push(linedefs);
+        }
+
+        return Ok(Some(Blockmap {
+            x_origin,
+            y_origin,
+            columns,
+            rows,
+            cells,
+        }));
+    }
+
+    // Gets the column and row of the block a point is in, if it's in the grid at all
+    pub fn cell_at(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let column = ((x - self.x_origin as f64) / BLOCK_SIZE).floor();
+        let row = ((y - self.y_origin as f64) / BLOCK_SIZE).floor();
+
+        if column < 0.0 || row < 0.0 || column >= self.columns as f64 || row >= self.rows as f64 {
+            return None;
+        }
+
+        return Some((column as usize, row as usize));
+    }
+
+    // Linedefs listed in a block
+    pub fn cell(&self, column: usize, row: usize) -> &[usize] {
+        if column >= self.columns || row >= self.rows {
+            return &[];
+        }
+
+        return &self.cells[row * self.columns + column];
+    }
+
+    // Linedefs in the block containing a point
+    pub fn linedefs_at(&self, loc: &Vertex) -> &[usize] {
+        match self.cell_at(loc.x, loc.y) {
+            Some((column, row)) => self.cell(column, row),
+            None => &[],
+        }
+    }
+
+    // Linedefs in every block a line passes through, without duplicates
+    pub fn linedefs_on_line(&self, start: &Vertex, end: &Vertex) -> Vec<usize> {
+        let mut linedefs: Vec<usize> = Vec::new();
+
+        for (column, row) in self.cells_on_line(start, end) {
+            for linedef in self.cell(column, row) {
+                if !linedefs.contains(linedef) {
+                    linedefs.push(*linedef);
+                }
+            }
+        }
+
+        return linedefs;
+    }
+
+    // Walks along a line one block at a time and returns the blocks it goes through in order
+    pub fn cells_on_line(&self, start: &Vertex, end: &Vertex) -> Vec<(usize, usize)> {
+        let mut cells: Vec<(usize, usize)> = Vec::new();
+
+        // Positions in block units relative to the grid
+        let x1 = (start.x - self.x_origin as f64) / BLOCK_SIZE;
+        let y1 = (start.y - self.y_origin as f64) / BLOCK_SIZE;
+        let x2 = (end.x - self.x_origin as f64) / BLOCK_SIZE;
+        let y2 = (end.y - self.y_origin as f64) / BLOCK_SIZE;
+
+        let mut column = x1.floor() as i64;
+        let mut row = y1.floor() as i64;
+        let last_column = x2.floor() as i64;
+        let last_row = y2.floor() as i64;
+
+        let step_x: i64 = if x2 > x1 { 1 } else { -1 };
+        let step_y: i64 = if y2 > y1 { 1 } else { -1 };
+
+        // How far along the line (from 0 to 1) it has to go to cross one whole block
+        let delta_x = if x2 != x1 {
+            1.0 / (x2 - x1).abs()
+        } else {
+            f64::INFINITY
+        };
+        let delta_y = if y2 != y1 {
+            1.0 / (y2 - y1).abs()
+        } else {
+            f64::INFINITY
+        };
+
+        // How far along the line the next block edge is
+        // Lines that don't move on an axis never reach an edge on it, multiplying would give 0 * infinity
+        // for lines right on a block edge
+        let mut next_x = if delta_x.is_infinite() {
+            f64::INFINITY
+        } else if x2 > x1 {
+            (x1.floor() + 1.0 - x1) * delta_x
+        } else {
+            (x1 - x1.floor()) * delta_x
+        };
+        let mut next_y = if delta_y.is_infinite() {
+            f64::INFINITY
+        } else if y2 > y1 {
+            (y1.floor() + 1.0 - y1) * delta_y
+        } else {
+            (y1 - y1.floor()) * delta_y
+        };
+
+        let steps = (last_column - column).abs() + (last_row - row).abs();
+        for _ in 0..=steps {
+            if column >= 0
+                && row >= 0
+                && (column as usize) < self.columns
+                && (row as usize) < self.rows
+            {
+                cells.push((column as usize, row as usize));
+            }
+
+            if next_x < next_y {
+                next_x += delta_x;
+                column += step_x;
+            } else {
+                next_y += delta_y;
+                row += step_y;
+            }
+        }
+
+        return cells;
+    }
+
+    // Highest linedef index in the blockmap, used to check it against the map
+    pub(crate) fn max_linedef(&self) -> Option<usize> {
+        return self.cells.iter().flatten().max().copied();
+    }
+
+    // Makes a blockmap for some linedefs, with the grid starting a bit below and left of the map
+    // like ZDBSP and most other node builders do
+    pub fn build(linedefs: &[LineDef], vertices: &[Vertex]) -> Result<Blockmap, WadError> {
+        let mut min_x = f64::MAX;
+        let mut min_y = f64::MAX;
+        let mut max_x = f64::MIN;
+        let mut max_y = f64::MIN;
+        for linedef in linedefs.iter() {
+            for index in [linedef.start, linedef.end] {
+                if let Some(vertex) = vertices.get(index as usize) {
+                    min_x = min_x.min(vertex.x);
+                    min_y = min_y.min(vertex.y);
+                    max_x = max_x.max(vertex.x);
+                    max_y = max_y.max(vertex.y);
+                }
+            }
+        }
+        if min_x > max_x {
+            min_x = 0.0;
+            min_y = 0.0;
+            max_x = 0.0;
+            max_y = 0.0;
+        }
+
+        let x_origin = min_x.floor() - 8.0;
+        let y_origin = min_y.floor() - 8.0;
+        if x_origin < i16::MIN as f64 || y_origin < i16::MIN as f64 {
+            return Err(WadError::TooBig {
+                name: "BLOCKMAP".to_string(),
+                reason: format!("origin {}, {} doesn't fit in 16 bits", x_origin, y_origin),
+            });
+        }
+
+        let mut blockmap = Blockmap {
+            x_origin: x_origin as i16,
+            y_origin: y_origin as i16,
+            columns: ((max_x - x_origin) / BLOCK_SIZE).floor() as usize + 1,
+            rows: ((max_y - y_origin) / BLOCK_SIZE).floor() as usize + 1,
+            cells: Vec::new(),
+        };
+        blockmap.cells = vec![Vec::new(); blockmap.columns * blockmap.rows];
+
+        for (i, linedef) in linedefs.iter().enumerate() {
+            let start = vertices.get(linedef.start as usize);
+            let end = vertices.get(linedef.end as usize);
+            if let (Some(start), Some(end)) = (start, end) {
+                for (column, row) in blockmap.cells_on_line(start, end) {
+                    blockmap.cells[row * blockmap.columns + column].push(i);
+                }
+            }
+        }
+
+        return Ok(blockmap);
+    }
+
+    // The blockmap as it would be stored in a BLOCKMAP lump
+    // Blocks with the same list share it to keep the lump small, since offsets are only 16 bits
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WadError> {
+        return self.lump_bytes(0xFFFF);
+    }
+
+    // Same as to_bytes but fails if the lump would only work in Boom and later ports,
+    // vanilla reads offsets as signed so it can't get to lists past word 32767
+    pub fn to_vanilla_bytes(&self) -> Result<Vec<u8>, WadError> {
+        return self.lump_bytes(0x7FFF);
+    }
+
+    fn lump_bytes(&self, offset_limit: usize) -> Result<Vec<u8>, WadError> {
+        let too_big = |reason: String| WadError::TooBig {
+            name: "BLOCKMAP".to_string(),
+            reason,
+        };
+
+        if self.columns > 0xFFFF || self.rows > 0xFFFF {
+            return Err(too_big(format!(
+                "{} by {} blocks is too many",
+                self.columns, self.rows
+            )));
+        }
+
+        // Header and offsets come first, then the lists
+        let mut words: Vec<u16> = vec![
+            self.x_origin as u16,
+            self.y_origin as u16,
+            self.columns as u16,
+            self.rows as u16,
+        ];
+        words.resize(4 + self.columns * self.rows, 0);
+
+        let mut list_offsets: HashMap<&Vec<usize>, usize> = HashMap::new();
+        for (i, cell) in self.cells.iter().enumerate() {
+            let list_loc = match list_offsets.get(cell) {
+                Some(&list_loc) => list_loc,
+                None => {
+                    let list_loc = words.len();
+
+                    // Every list starts with a 0, which vanilla checks as linedef 0 so linedef 0 ends up tested
+                    // in every block. Boom skips it but it has to stay there for vanilla and tools that expect it
+                    words.push(0);
+                    for linedef in cell.iter() {
+                        if *linedef >= 0xFFFF {
+                            return Err(too_big(format!(
+                                "linedef {} can't be stored in 16 bits",
+                                linedef
+                            )));
+                        }
+                        words.push(*linedef as u16);
+                    }
+                    words.push(0xFFFF);
+
+                    list_offsets.insert(cell, list_loc);
+                    list_loc
+                }
+            };
+
+            if list_loc > offset_limit {
+                return Err(too_big(format!(
+                    "lists go past the {} word limit at block {}",
+                    offset_limit, i
+                )));
+            }
+            words[4 + i] = list_loc as u16;
+        }
+
+        let mut bytes: Vec<u8> = vec![0; words.len() * 2];
+        <LittleEndian as ByteOrder>::write_u16_into(&words, &mut bytes);
+
+        return Ok(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::LineDefFlags;
+
+    fn linedef(start: u32, end: u32) -> LineDef {
+        return LineDef {
+            start,
+            end,
+            special_type: 0,
+            sector_tag: 0,
+            flags: LineDefFlags::empty(),
+            args: [0; 5],
+            front_sidedef: Some(0),
+            back_sidedef: None,
+        };
+    }
+
+    #[test]
+    fn reads_lists_after_the_leading_0() {
+        // 2 by 1 blocks at the origin, the second block's list is empty
+        let words: Vec<u16> = vec![0, 0, 2, 1, 6, 9, 0, 3, 0xFFFF, 0, 0xFFFF];
+        let mut data = vec![0u8; words.len() * 2];
+        <LittleEndian as ByteOrder>::write_u16_into(&words, &mut data);
+
+        let blockmap = Blockmap::from_bytes(&data).unwrap().unwrap();
+        assert_eq!(blockmap.cell(0, 0), &[3]);
+        assert!(blockmap.cell(1, 0).is_empty());
+        assert_eq!(blockmap.linedefs_at(&Vertex { x: 10.0, y: 10.0 }), &[3]);
+        assert_eq!(blockmap.cell_at(-1.0, 10.0), None);
+        assert_eq!(blockmap.cell_at(200.0, 10.0), Some((1, 0)));
+    }
+
+    #[test]
+    fn lists_past_the_end_are_errors() {
+        let words: Vec<u16> = vec![0, 0, 1, 1, 5, 0, 3];
+        let mut data = vec![0u8; words.len() * 2];
+        <LittleEndian as ByteOrder>::write_u16_into(&words, &mut data);
+
+        assert!(matches!(
+            Blockmap::from_bytes(&data),
+            Err(WadError::MalformedLump { .. })
+        ));
+    }
+
+    #[test]
+    fn built_blockmaps_come_back_the_same() {
+        // A room 3 blocks wide and 1 high with a diagonal line across it
+        let vertices = vec![
+            Vertex { x: 0.0, y: 0.0 },
+            Vertex { x: 0.0, y: 100.0 },
+            Vertex { x: 300.0, y: 100.0 },
+            Vertex { x: 300.0, y: 0.0 },
+        ];
+        let linedefs = vec![
+            linedef(0, 1),
+            linedef(1, 2),
+            linedef(2, 3),
+            linedef(3, 0),
+            linedef(0, 2),
+        ];
+
+        let built = Blockmap::build(&linedefs, &vertices).unwrap();
+        assert_eq!((built.x_origin, built.y_origin), (-8, -8));
+        assert_eq!((built.columns, built.rows), (3, 1));
+        assert_eq!(built.cell(0, 0), &[0, 1, 3, 4]);
+        assert_eq!(built.cell(1, 0), &[1, 3, 4]);
+        assert_eq!(built.cell(2, 0), &[1, 2, 3, 4]);
+
+        let read = Blockmap::from_bytes(&built.to_bytes().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (read.x_origin, read.y_origin),
+            (built.x_origin, built.y_origin)
+        );
+        assert_eq!((read.columns, read.rows), (built.columns, built.rows));
+        for column in 0..built.columns {
+            assert_eq!(read.cell(column, 0), built.cell(column, 0));
+        }
+    }
+
+    #[test]
+    fn blocks_with_the_same_list_share_it() {
+        let vertices = vec![Vertex { x: 0.0, y: 0.0 }, Vertex { x: 1000.0, y: 0.0 }];
+        let blockmap = Blockmap::build(&[linedef(0, 1)], &vertices).unwrap();
+
+        // Header, one offset per block, then a single list of 0, the linedef and -1
+        let bytes = blockmap.to_bytes().unwrap();
+        assert_eq!(bytes.len(), (4 + blockmap.columns * blockmap.rows + 3) * 2);
+    }
+
+    #[test]
+    fn lines_on_block_edges_walk_along_them() {
+        let blockmap = Blockmap {
+            x_origin: -8,
+            y_origin: -8,
+            columns: 4,
+            rows: 4,
+            cells: vec![Vec::new(); 16],
+        };
+
+        let horizontal =
+            blockmap.cells_on_line(&Vertex { x: 0.0, y: 120.0 }, &Vertex { x: 400.0, y: 120.0 });
+        assert_eq!(horizontal, vec![(0, 1), (1, 1), (2, 1), (3, 1)]);
+
+        let vertical =
+            blockmap.cells_on_line(&Vertex { x: 248.0, y: 400.0 }, &Vertex { x: 248.0, y: 0.0 });
+        assert_eq!(vertical, vec![(2, 3), (2, 2), (2, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn walls_on_block_edges_are_in_every_block_they_cross() {
+        // A room 4 blocks wide and 2 high, the grid starts at -8 so y = 120 and x = 248 are block edges
+        let vertices = vec![
+            Vertex { x: 0.0, y: 0.0 },
+            Vertex { x: 0.0, y: 240.0 },
+            Vertex { x: 400.0, y: 240.0 },
+            Vertex { x: 400.0, y: 0.0 },
+            Vertex { x: 0.0, y: 120.0 },
+            Vertex { x: 400.0, y: 120.0 },
+            Vertex { x: 248.0, y: 0.0 },
+            Vertex { x: 248.0, y: 240.0 },
+        ];
+        let linedefs = vec![
+            linedef(0, 1),
+            linedef(1, 2),
+            linedef(2, 3),
+            linedef(3, 0),
+            linedef(4, 5),
+            linedef(6, 7),
+        ];
+
+        let built = Blockmap::build(&linedefs, &vertices).unwrap();
+        let read = Blockmap::from_bytes(&built.to_bytes().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!((read.columns, read.rows), (4, 2));
+        for column in 0..4 {
+            assert!(read.cell(column, 1).contains(&4));
+        }
+        for row in 0..2 {
+            assert!(read.cell(2, row).contains(&5));
+        }
+    }
+
+    #[test]
+    fn offsets_past_32767_only_work_in_boom() {
+        // Every block gets its own list of 3 words, so 100 by 100 blocks need offsets past 32767 words
+        let size = 100;
+        let blockmap = Blockmap {
+            x_origin: 0,
+            y_origin: 0,
+            columns: size,
+            rows: size,
+            cells: (0..size * size).map(|i| vec![i]).collect(),
+        };
+
+        assert!(blockmap.to_bytes().is_ok());
+        assert!(matches!(
+            blockmap.to_vanilla_bytes(),
+            Err(WadError::TooBig { .. })
+        ));
+    }
+}
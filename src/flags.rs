@@ -0,0 +1,142 @@
+use bitflags::bitflags;
+
+// Games that use different meanings for the same flag bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlagLayout {
+    Doom,   // Doom, Heretic, Boom and MBF21
+    Hexen,  // Hexen and ZDoom's Hexen format
+    Strife, // Only comes from UDMF since binary Strife maps look like Doom ones
+}
+
+// Difficulty levels, Doom only has 3 skill bits so the easiest two and hardest two share one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skill {
+    TooYoungToDie,
+    NotTooRough,
+    HurtMePlenty,
+    UltraViolence,
+    Nightmare,
+}
+
+// The kinds of game a thing can be set to show up in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameMode {
+    Single,
+    Coop,
+    Deathmatch,
+}
+
+bitflags! {
+    // Options on a thing, bits that don't have a name here are still kept
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ThingFlags: u16 {
+        const EASY = 0x0001;
+        const MEDIUM = 0x0002;
+        const HARD = 0x0004;
+        const AMBUSH = 0x0008; // Deaf, waits until it sees the player
+        const MULTIPLAYER = 0x0010; // Not in single player
+
+        // Boom and MBF
+        const NOT_DEATHMATCH = 0x0020;
+        const NOT_COOP = 0x0040;
+        const FRIEND = 0x0080;
+
+        // Boom ignores the flags above if this is set since old editors left garbage in the high bits
+        const RESERVED = 0x0100;
+
+        // Hexen uses the bits after ambush differently
+        const HEXEN_DORMANT = 0x0010;
+        const HEXEN_FIGHTER = 0x0020;
+        const HEXEN_CLERIC = 0x0040;
+        const HEXEN_MAGE = 0x0080;
+        const HEXEN_SINGLE = 0x0100;
+        const HEXEN_COOP = 0x0200;
+        const HEXEN_DEATHMATCH = 0x0400;
+
+        // So does Strife
+        const STRIFE_STANDING = 0x0008;
+        const STRIFE_MULTIPLAYER = 0x0010;
+        const STRIFE_AMBUSH = 0x0020;
+        const STRIFE_ALLY = 0x0040;
+        const STRIFE_TRANSLUCENT = 0x0100;
+        const STRIFE_INVISIBLE = 0x0200;
+
+        const _ = !0;
+    }
+}
+
+bitflags! {
+    // Flags on a linedef, bits that don't have a name here are still kept
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct LineDefFlags: u16 {
+        const BLOCKING = 0x0001; // Blocks players and monsters
+        const BLOCK_MONSTERS = 0x0002;
+        const TWO_SIDED = 0x0004;
+        const UPPER_UNPEGGED = 0x0008;
+        const LOWER_UNPEGGED = 0x0010;
+        const SECRET = 0x0020; // Shows up as one sided on the automap
+        const BLOCK_SOUND = 0x0040;
+        const NEVER_ON_MAP = 0x0080;
+        const ALWAYS_ON_MAP = 0x0100;
+
+        // Boom, lets a use go through to lines behind this one
+        const PASS_USE = 0x0200;
+
+        // MBF21
+        const BLOCK_LAND_MONSTERS = 0x1000;
+        const BLOCK_PLAYERS = 0x2000;
+
+        // Hexen uses the bits after the automap ones for when the special happens
+        const HEXEN_REPEAT_SPECIAL = 0x0200;
+        const HEXEN_ACTIVATION = 0x1C00;
+        const HEXEN_MONSTERS_ACTIVATE = 0x2000;
+        const HEXEN_BLOCK_EVERYTHING = 0x8000;
+
+        // Strife
+        const STRIFE_JUMP_OVER = 0x0200;
+        const STRIFE_BLOCK_FLOATERS = 0x0400;
+        const STRIFE_TRANSLUCENT = 0x0800;
+
+        const _ = !0;
+    }
+}
+
+impl ThingFlags {
+    // Checks if a thing with these flags gets spawned in a game
+    pub fn appears_on(&self, layout: FlagLayout, skill: Skill, mode: GameMode) -> bool {
+        let skill_flag = match skill {
+            Skill::TooYoungToDie | Skill::NotTooRough => ThingFlags::EASY,
+            Skill::HurtMePlenty => ThingFlags::MEDIUM,
+            Skill::UltraViolence | Skill::Nightmare => ThingFlags::HARD,
+        };
+        if !self.contains(skill_flag) {
+            return false;
+        }
+
+        match layout {
+            FlagLayout::Doom => {
+                let mut flags = *self;
+                if flags.contains(ThingFlags::RESERVED) {
+                    flags.remove(
+                        ThingFlags::NOT_DEATHMATCH | ThingFlags::NOT_COOP | ThingFlags::FRIEND,
+                    );
+                }
+
+                match mode {
+                    GameMode::Single => !flags.contains(ThingFlags::MULTIPLAYER),
+                    GameMode::Coop => !flags.contains(ThingFlags::NOT_COOP),
+                    GameMode::Deathmatch => !flags.contains(ThingFlags::NOT_DEATHMATCH),
+                }
+            }
+            // Hexen says which modes a thing is in instead of which ones it isn't
+            FlagLayout::Hexen => match mode {
+                GameMode::Single => self.contains(ThingFlags::HEXEN_SINGLE),
+                GameMode::Coop => self.contains(ThingFlags::HEXEN_COOP),
+                GameMode::Deathmatch => self.contains(ThingFlags::HEXEN_DEATHMATCH),
+            },
+            FlagLayout::Strife => {
+                mode != GameMode::Single || !self.contains(ThingFlags::STRIFE_MULTIPLAYER)
+            }
+        }
+    }
+}
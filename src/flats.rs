@@ -0,0 +1,159 @@
+use crate::picture::Picture;
+use crate::wad::{LumpName, Namespace, WadError};
+use crate::wad_stack::WadStack;
+use std::collections::HashMap;
+
+// Flats are the floor and ceiling textures, always 64 by 64 palette indexes with no header
+pub const FLAT_SIZE: usize = 64;
+
+// Ceilings with this flat show the sky instead
+pub const SKY_FLAT: &str = "F_SKY1";
+
+pub fn is_sky_flat(name: &LumpName) -> bool {
+    return name == SKY_FLAT;
+}
+
+// Decodes a flat into a solid picture
+// Only the first 64 by 64 pixels get used like in the engine, Heretic and Hexen have some padded ones
+pub fn read_flat(name: &str, data: &[u8]) -> Result<Picture, WadError> {
+    if data.len() < FLAT_SIZE * FLAT_SIZE {
+        return Err(WadError::MalformedLump {
+            name: name.to_string(),
+            offset: 0,
+            reason: format!(
+                "{} bytes is too small for a {} by {} flat",
+                data.len(),
+                FLAT_SIZE,
+                FLAT_SIZE
+            ),
+        });
+    }
+
+    let pixels: Vec<Option<u8>> = data[..FLAT_SIZE * FLAT_SIZE]
+        .iter()
+        .map(|index| Some(*index))
+        .collect();
+
+    return Ok(Picture::new(FLAT_SIZE, FLAT_SIZE, pixels));
+}
+
+// Every flat in the loaded files, decoded the first time it's asked for
+// Flats are found again with the same WadStack they were loaded from, so it has to be
+// loaded again if the files change
+pub struct Flats {
+    // Which file each flat is in and where it is in that file's directory, in the order they're
+    // in the files since animated flats like NUKAGE1 to NUKAGE3 depend on it
+    lumps: Vec<(usize, usize)>,
+    names: Vec<LumpName>,
+
+    by_name: HashMap<LumpName, usize>,
+    cache: HashMap<LumpName, Picture>,
+}
+
+impl Flats {
+    // Finds the flats in every file, flats in PWADs replace the ones with the same name
+    pub fn load(wads: &WadStack) -> Flats {
+        let lumps = wads.namespace_indexes(Namespace::Flats);
+
+        let mut names: Vec<LumpName> = Vec::new();
+        let mut by_name: HashMap<LumpName, usize> = HashMap::new();
+        for (i, (wad, lump)) in lumps.iter().enumerate() {
+            let name = LumpName::new(wads.lump_at(*wad, *lump).name());
+            by_name.insert(name.clone(), i);
+            names.push(name);
+        }
+
+        return Flats {
+            lumps,
+            names,
+            by_name,
+            cache: HashMap::new(),
+        };
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        return self.names.iter().map(|name| name.as_str()).collect();
+    }
+
+    // Position of a flat in the merged list
+    pub fn index(&self, name: &LumpName) -> Option<usize> {
+        return self.by_name.get(name).copied();
+    }
+
+    // Gets a flat by name, None if no file has it
+    pub fn get(&mut self, wads: &WadStack, name: &LumpName) -> Result<Option<&Picture>, WadError> {
+        let index = match self.by_name.get(name) {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+
+        if !self.cache.contains_key(name) {
+            let (wad, lump) = self.lumps[index];
+            let lump = wads.lump_at(wad, lump);
+            let flat = read_flat(lump.name(), lump.data())?;
+            self.cache.insert(name.clone(), flat);
+        }
+
+        return Ok(self.cache.get(name));
+    }
+
+    // Throws away every decoded flat
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wad::Wad;
+    use crate::writer::WadWriter;
+
+    fn flats_wad(wad_id: &str, flats: &[(&str, u8)]) -> Wad {
+        let mut writer = WadWriter::new(wad_id);
+        writer.add_lump("F_START", Vec::new());
+        for (name, index) in flats.iter() {
+            writer.add_lump(name, vec![*index; FLAT_SIZE * FLAT_SIZE]);
+        }
+        writer.add_lump("F_END", Vec::new());
+
+        return Wad::from_bytes(writer.to_bytes()).unwrap();
+    }
+
+    #[test]
+    fn pwad_flats_replace_ones_with_the_same_name() {
+        let mut wads = WadStack::from_iwad(
+            "iwad.wad",
+            flats_wad("IWAD", &[("NUKAGE1", 1), ("F_SKY1", 2)]),
+        )
+        .unwrap();
+        wads.add_wad(
+            "pwad.wad",
+            flats_wad("PWAD", &[("NUKAGE1", 3), ("NEWFLAT", 4)]),
+        );
+
+        let mut flats = Flats::load(&wads);
+        assert_eq!(flats.names(), vec!["NUKAGE1", "F_SKY1", "NEWFLAT"]);
+        assert_eq!(flats.index(&LumpName::new("NEWFLAT")), Some(2));
+        assert!(is_sky_flat(&LumpName::new(flats.names()[1])));
+
+        let flat = flats
+            .get(&wads, &LumpName::new("NUKAGE1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(flat.pixel(63, 63), Some(3));
+        assert!(flats
+            .get(&wads, &LumpName::new("NOFLAT"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn short_flats_are_errors() {
+        assert!(read_flat("SHORT", &[0; 100]).is_err());
+
+        // Only the first 64 by 64 of a padded flat is used
+        let flat = read_flat("PADDED", &[7; 4096 + 64]).unwrap();
+        assert_eq!((flat.width, flat.height), (FLAT_SIZE, FLAT_SIZE));
+    }
+}
@@ -1,5 +1,24 @@
+mod blockmap;
+mod flags;
+mod flats;
+mod nodebuilder;
+mod nodes;
+mod palette;
+mod picture;
+mod pk3;
+mod reject;
+mod sprites;
+mod textures;
+mod things;
+mod udmf;
 mod wad;
+mod wad_stack;
+mod writer;
 use macroquad::prelude::*;
+use palette::{Colormap, Palette};
+
+// Palette index walls get drawn with before they're lit, white in Doom's palette
+const WALL_COLOR: u8 = 4;
 
 // Config for window
 fn conf() -> Conf {
@@ -18,30 +37,88 @@ fn conf() -> Conf {
 #[macroquad::main(conf)]
 async fn main() {
     // Wad loading stuff
-    let wad_file = wad::Wad::load("assets/freedoom1.wad");
-
-    if wad_file.wad_id != "IWAD".to_string() {
-        panic!("not an IWAD");
+    let mut wads = match wad_stack::WadStack::new("assets/freedoom1.wad") {
+        Ok(wads) => wads,
+        Err(err) => panic!("Couldn't load IWAD: {}", err),
+    };
+
+    // Any PWADs given on the command line get loaded on top of the IWAD
+    for path in std::env::args().skip(1) {
+        if let Err(err) = wads.add_pwad(&path) {
+            panic!("Couldn't load PWAD: {}", err);
+        }
     }
 
-    let e1m1 = wad_file.maps.get("E1M1\0\0\0\0").unwrap();
-    // Get spawn location
-    let mut loc = &e1m1.p1_spawn;
-    let mut rot = e1m1.p1_rot as f64;
+    // Colours and light levels
+    let palette = match Palette::load(&wads) {
+        Ok(palette) => palette,
+        Err(err) => panic!("Couldn't load palette: {}", err),
+    };
+    let colormap = match Colormap::load(&wads) {
+        Ok(colormap) => colormap,
+        Err(err) => panic!("Couldn't load colormap: {}", err),
+    };
+
+    // Maps only get parsed when they're asked for so this is where a broken map shows up
+    let e1m1 = match wads.map("E1M1") {
+        Ok(Some(map)) => map,
+        Ok(None) => panic!("Couldn't find E1M1"),
+        Err(err) => panic!("Couldn't load E1M1: {}", err),
+    };
+    // Get spawn locations, starting from player 1's if there is one
+    let starts = e1m1.starts();
+    if starts.is_empty() {
+        panic!("E1M1 doesn't have anywhere to start");
+    }
+    let mut current_start = match e1m1.player_start(1) {
+        Some(start) => starts.iter().position(|s| *s == start).unwrap_or(0),
+        None => 0,
+    };
 
     loop {
         clear_background(BLACK);
 
-        let sorted_ssecs = e1m1.traverse_bsp(&e1m1.nodes.len() - 1, loc);
+        // Tab goes to the next start and shift tab goes back
+        if is_key_pressed(KeyCode::Tab) {
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                current_start = (current_start + starts.len() - 1) % starts.len();
+            } else {
+                current_start = (current_start + 1) % starts.len();
+            }
+        }
+        let loc = &starts[current_start].location;
+        let rot = starts[current_start].angle as f64;
+
+        let sorted_ssecs = e1m1.traverse_from_root(loc);
         let cut_segs = e1m1.cut_nonvis(&sorted_ssecs, loc, rot);
         println!("{}", cut_segs.len());
-        draw_segs(e1m1, cut_segs, loc, rot);
+        draw_segs(e1m1, &palette, &colormap, cut_segs, loc, rot);
+
+        draw_text(
+            &format!(
+                "{:?} ({}/{})",
+                starts[current_start].kind,
+                current_start + 1,
+                starts.len()
+            ),
+            10.0,
+            20.0,
+            20.0,
+            WHITE,
+        );
 
         next_frame().await;
     }
 }
 
-fn draw_segs(map: &wad::BspMap, segs: Vec<&wad::Seg>, loc: &wad::Vertex, rot: f64) {
+fn draw_segs(
+    map: &wad::BspMap,
+    palette: &Palette,
+    colormap: &Colormap,
+    segs: Vec<&wad::Seg>,
+    loc: &wad::Vertex,
+    rot: f64,
+) {
     // Gives which columns are able to be drawn on
     let mut free: Vec<usize> = (0..640).collect();
 
@@ -55,8 +132,8 @@ fn draw_segs(map: &wad::BspMap, segs: Vec<&wad::Seg>, loc: &wad::Vertex, rot: f6
 
         // Get linedef info
         let linedef = &map.linedefs[seg.linedef_num as usize];
-        let has_front = linedef.front_sidedef != -1;
-        let has_back = linedef.back_sidedef != -1;
+        let has_front = linedef.front_sidedef.is_some();
+        let has_back = linedef.back_sidedef.is_some();
 
         let direction = seg.direction;
 
@@ -74,14 +151,19 @@ fn draw_segs(map: &wad::BspMap, segs: Vec<&wad::Seg>, loc: &wad::Vertex, rot: f6
         // draw_line(end_x, 0.0, end_x, 480.0, 1.0, WHITE);
 
         if seg.direction {
-            let back_sidedef = &map.sidedefs[linedef.back_sidedef as usize];
+            // Segs on the back of a line only exist if it has a back side
+            let back_sidedef = match linedef.back_sidedef {
+                Some(back_sidedef) => &map.sidedefs[back_sidedef as usize],
+                None => continue,
+            };
             let back_sector = &map.sectors[back_sidedef.facing_sector as usize];
 
             let floor_height = back_sector.floor_height - 41;
             let ceiling_height = back_sector.ceiling_height - 41;
-            let dist = ((start.x - loc.x).pow(2) as f64 + (start.y - loc.y).pow(2) as f64).sqrt();
+            let dist = ((start.x - loc.x).powi(2) + (start.y - loc.y).powi(2)).sqrt();
 
             let scale = (90.0 - start_angle + rot).sin() * dist;
+            let color = lit_color(palette, colormap, back_sector.light_level, dist);
 
             draw_line(
                 start_x,
@@ -89,19 +171,21 @@ fn draw_segs(map: &wad::BspMap, segs: Vec<&wad::Seg>, loc: &wad::Vertex, rot: f6
                 start_x,
                 (ceiling_height as f64 / scale) as f32 * 320.0 - 320.0,
                 1.0,
-                WHITE,
+                color,
             )
         } else {
-            let front_sidedef = &map.sidedefs[linedef.front_sidedef as usize];
+            let front_sidedef = match linedef.front_sidedef {
+                Some(front_sidedef) => &map.sidedefs[front_sidedef as usize],
+                None => continue,
+            };
             let front_sector = &map.sectors[front_sidedef.facing_sector as usize];
 
             let floor_height = front_sector.floor_height - 41;
             let ceiling_height = front_sector.ceiling_height - 41;
-            let dist = (((start.x - loc.x) as f64).powi(2) as f64
-                + ((start.y - loc.y) as f64).powi(2))
-            .sqrt();
+            let dist = ((start.x - loc.x).powi(2) + (start.y - loc.y).powi(2)).sqrt();
 
             let scale = (90.0 - start_angle + rot).sin() * dist;
+            let color = lit_color(palette, colormap, front_sector.light_level, dist);
 
             draw_line(
                 start_x,
@@ -109,7 +193,7 @@ fn draw_segs(map: &wad::BspMap, segs: Vec<&wad::Seg>, loc: &wad::Vertex, rot: f6
                 start_x,
                 (ceiling_height as f64 / scale) as f32 * 320.0 + 320.0,
                 1.0,
-                WHITE,
+                color,
             );
             draw_line(
                 end_x,
@@ -117,12 +201,18 @@ fn draw_segs(map: &wad::BspMap, segs: Vec<&wad::Seg>, loc: &wad::Vertex, rot: f6
                 end_x,
                 (ceiling_height as f64 / scale) as f32 * 320.0 + 320.0,
                 1.0,
-                WHITE,
+                color,
             );
         }
     }
 }
 
+// Colour of a wall in a sector with some light level at a distance
+fn lit_color(palette: &Palette, colormap: &Colormap, light_level: i16, dist: f64) -> Color {
+    let [r, g, b, a] = palette.lit_color(colormap, WALL_COLOR, light_level, dist);
+    return Color::from_rgba(r, g, b, a);
+}
+
 fn angle_to_x(angle: f64) -> f32 {
     return (angle.to_radians().tan()) as f32 * 320.0 + 320.0;
 }
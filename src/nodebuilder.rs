@@ -0,0 +1,608 @@
+use crate::nodes::{seg_angle, seg_offset, ExtendedNodes, NodeFormat};
+use crate::wad::{LineDef, Node, Sector, Seg, SideDef, SubSector, Vertex};
+use std::collections::HashSet;
+
+// Anything closer to a partition line than this counts as being on it,
+// so tiny rounding errors don't make slivers of segs
+const EPSILON: f64 = 0.001;
+
+// Knobs for how the builder picks partition lines, a lower cost is a better partition
+pub struct BuildOptions {
+    // Cost of every seg that has to be cut in two
+    pub split_cost: f64,
+
+    // Cost of every seg more on one side than the other, keeps the tree from getting too deep
+    pub balance_cost: f64,
+
+    // Extra cost for lines that aren't horizontal or vertical
+    pub diagonal_cost: f64,
+
+    // How many partition lines to try at each node, 0 tries all of them
+    // Big maps build a lot faster with a limit but the tree won't be as good
+    pub max_candidates: usize,
+}
+
+impl Default for BuildOptions {
+    fn default() -> BuildOptions {
+        return BuildOptions {
+            split_cost: 8.0,
+            balance_cost: 1.0,
+            diagonal_cost: 2.0,
+            max_candidates: 0,
+        };
+    }
+}
+
+// A seg while the tree is being built
+#[derive(Clone)]
+struct BuildSeg {
+    start: u32,
+    end: u32,
+    linedef: usize,
+    direction: bool,
+    sector: u32, // Sector the seg's sidedef faces
+}
+
+// A partition line, stored the same way it will be in the node so splitting and traversing agree
+struct Partition {
+    x: f64,
+    y: f64,
+    dx: f64,
+    dy: f64,
+}
+
+impl Partition {
+    // Distance from the line to a point, negative on the right and positive on the left
+    fn distance(&self, loc: &Vertex) -> f64 {
+        let cross = self.dx * (loc.y - self.y) - self.dy * (loc.x - self.x);
+        let distance = cross / (self.dx * self.dx + self.dy * self.dy).sqrt();
+        if distance.abs() < EPSILON {
+            return 0.0;
+        }
+
+        return distance;
+    }
+}
+
+// Which side of a partition a seg ends up on
+enum Side {
+    Right,
+    Left,
+    Split,
+}
+
+struct Builder<'a> {
+    options: &'a BuildOptions,
+    linedefs: &'a [LineDef],
+    vertices: Vec<Vertex>,
+    segs: Vec<Seg>,
+    subsectors: Vec<SubSector>,
+    nodes: Vec<Node>,
+}
+
+// Builds the BSP tree for a map from its linedefs, sidedefs, sectors and vertices
+// Split vertices go after the map's own vertices in new_vertices, the same as ZDoom's extended nodes
+pub fn build_nodes(
+    linedefs: &[LineDef],
+    sidedefs: &[SideDef],
+    sectors: &[Sector],
+    vertices: &[Vertex],
+    options: &BuildOptions,
+) -> ExtendedNodes {
+    let mut builder = Builder {
+        options,
+        linedefs,
+        vertices: vertices.to_vec(),
+        segs: Vec::new(),
+        subsectors: Vec::new(),
+        nodes: Vec::new(),
+    };
+
+    // Sides only get segs if their sidedef and its sector exist, otherwise the engine has nothing to draw them with
+    let side_sector = |sidedef: Option<u32>| -> Option<u32> {
+        let sector = sidedefs.get(sidedef? as usize)?.facing_sector;
+        if sector as usize >= sectors.len() {
+            return None;
+        }
+        return Some(sector);
+    };
+
+    // Every side of a linedef with a sidedef starts off as one seg
+    let mut segs: Vec<BuildSeg> = Vec::new();
+    for (i, linedef) in linedefs.iter().enumerate() {
+        let start = linedef.start;
+        let end = linedef.end;
+        if start as usize >= vertices.len() || end as usize >= vertices.len() {
+            continue;
+        }
+
+        // Zero length lines can't be drawn or split by
+        let (a, b) = (&vertices[start as usize], &vertices[end as usize]);
+        if a.x == b.x && a.y == b.y {
+            continue;
+        }
+
+        if let Some(sector) = side_sector(linedef.front_sidedef) {
+            segs.push(BuildSeg {
+                start,
+                end,
+                linedef: i,
+                direction: false,
+                sector,
+            });
+        }
+        if let Some(sector) = side_sector(linedef.back_sidedef) {
+            segs.push(BuildSeg {
+                start: end,
+                end: start,
+                linedef: i,
+                direction: true,
+                sector,
+            });
+        }
+    }
+
+    if !segs.is_empty() {
+        builder.build(segs);
+    }
+
+    return ExtendedNodes {
+        format: NodeFormat::Built,
+        new_vertices: builder.vertices[vertices.len()..].to_vec(),
+        segs: builder.segs,
+        subsectors: builder.subsectors,
+        nodes: builder.nodes,
+    };
+}
+
+impl<'a> Builder<'a> {
+    // Builds a subtree and returns whether it's a subsector, its index and its bounding box
+    // Children are always added before their parent so the root ends up as the last node
+    fn build(&mut self, mut segs: Vec<BuildSeg>) -> (bool, u32, Vec<i16>) {
+        let bounding_box = self.bounding_box(&segs);
+
+        let partition = match self.pick_partition(&segs) {
+            Some(partition) => partition,
+            None => {
+                // Nothing splits these segs so they already make a convex subsector
+                // The engine takes a subsector's sector from its first seg, so if a sector isn't closed and
+                // the segs face different sectors the one most of them face goes first
+                let count = |sector: u32| segs.iter().filter(|seg| seg.sector == sector).count();
+                if let Some(main) = (0..segs.len()).max_by_key(|&i| count(segs[i].sector)) {
+                    segs.swap(0, main);
+                }
+
+                let first_seg = self.segs.len() as u32;
+                let ssec_size = segs.len() as u32;
+                for seg in segs {
+                    self.add_seg(seg);
+                }
+                self.subsectors.push(SubSector {
+                    ssec_size,
+                    first_seg,
+                });
+
+                return (true, self.subsectors.len() as u32 - 1, bounding_box);
+            }
+        };
+
+        let mut right: Vec<BuildSeg> = Vec::new();
+        let mut left: Vec<BuildSeg> = Vec::new();
+        for seg in segs {
+            match self.side_of(&partition, &seg) {
+                Side::Right => right.push(seg),
+                Side::Left => left.push(seg),
+                Side::Split => {
+                    let (right_part, left_part) = self.split(&partition, seg);
+                    right.push(right_part);
+                    left.push(left_part);
+                }
+            }
+        }
+
+        let (right_is_ssec, right_index, right_box) = self.build(right);
+        let (left_is_ssec, left_index, left_box) = self.build(left);
+
+        self.nodes.push(Node {
+            start: vec![partition.x as i16, partition.y as i16],
+            change: vec![partition.dx as i16, partition.dy as i16],
+            right_box,
+            left_box,
+            right_is_ssec,
+            right_index,
+            left_is_ssec,
+            left_index,
+        });
+
+        return (false, self.nodes.len() as u32 - 1, bounding_box);
+    }
+
+    // The line a seg's linedef runs along, facing the same way as the seg
+    fn partition_for(&self, seg: &BuildSeg) -> Partition {
+        let linedef = &self.linedefs[seg.linedef];
+        let (from, to) = if seg.direction {
+            (linedef.end, linedef.start)
+        } else {
+            (linedef.start, linedef.end)
+        };
+        let from = &self.vertices[from as usize];
+        let to = &self.vertices[to as usize];
+
+        // Nodes only hold whole numbers so the line has to be rounded before it's used for anything
+        let x = from.x.round();
+        let y = from.y.round();
+        return Partition {
+            x,
+            y,
+            dx: to.x.round() - x,
+            dy: to.y.round() - y,
+        };
+    }
+
+    fn side_of(&self, partition: &Partition, seg: &BuildSeg) -> Side {
+        let start = partition.distance(&self.vertices[seg.start as usize]);
+        let end = partition.distance(&self.vertices[seg.end as usize]);
+
+        if start == 0.0 && end == 0.0 {
+            // Segs on the line go on the side they face
+            let seg_dx = self.vertices[seg.end as usize].x - self.vertices[seg.start as usize].x;
+            let seg_dy = self.vertices[seg.end as usize].y - self.vertices[seg.start as usize].y;
+            if seg_dx * partition.dx + seg_dy * partition.dy > 0.0 {
+                return Side::Right;
+            }
+            return Side::Left;
+        }
+        if start <= 0.0 && end <= 0.0 {
+            return Side::Right;
+        }
+        if start >= 0.0 && end >= 0.0 {
+            return Side::Left;
+        }
+
+        return Side::Split;
+    }
+
+    // Cuts a seg where it crosses the partition and returns the right part then the left part
+    fn split(&mut self, partition: &Partition, seg: BuildSeg) -> (BuildSeg, BuildSeg) {
+        let start = self.vertices[seg.start as usize];
+        let end = self.vertices[seg.end as usize];
+        let start_distance = partition.distance(&start);
+        let end_distance = partition.distance(&end);
+
+        let along = start_distance / (start_distance - end_distance);
+        self.vertices.push(Vertex {
+            x: start.x + (end.x - start.x) * along,
+            y: start.y + (end.y - start.y) * along,
+        });
+        let middle = self.vertices.len() as u32 - 1;
+
+        let first = BuildSeg {
+            end: middle,
+            ..seg.clone()
+        };
+        let second = BuildSeg {
+            start: middle,
+            ..seg
+        };
+
+        if start_distance < 0.0 {
+            return (first, second);
+        }
+        return (second, first);
+    }
+
+    // Finds the partition with the lowest cost, None if no line has segs on both sides of it
+    fn pick_partition(&self, segs: &[BuildSeg]) -> Option<Partition> {
+        // With a limit, candidates are spread out over the whole list instead of just the first few
+        let step = if self.options.max_candidates == 0 || segs.len() <= self.options.max_candidates
+        {
+            1
+        } else {
+            segs.len() / self.options.max_candidates
+        };
+
+        let best = self.best_partition(segs, step);
+        if best.is_none() && step > 1 {
+            // Checks everything before deciding the segs really are convex
+            return self.best_partition(segs, 1);
+        }
+
+        return best;
+    }
+
+    fn best_partition(&self, segs: &[BuildSeg], step: usize) -> Option<Partition> {
+        let mut best: Option<(f64, Partition)> = None;
+        let mut tried: HashSet<usize> = HashSet::new();
+
+        for candidate in segs.iter().step_by(step) {
+            // Both sides of a linedef are the same line
+            if !tried.insert(candidate.linedef) {
+                continue;
+            }
+
+            let partition = self.partition_for(candidate);
+            if partition.dx == 0.0 && partition.dy == 0.0 {
+                continue;
+            }
+
+            let mut right = 0;
+            let mut left = 0;
+            let mut splits = 0;
+            for seg in segs.iter() {
+                match self.side_of(&partition, seg) {
+                    Side::Right => right += 1,
+                    Side::Left => left += 1,
+                    Side::Split => {
+                        right += 1;
+                        left += 1;
+                        splits += 1;
+                    }
+                }
+            }
+
+            // A line with everything on one side doesn't split anything up
+            if right == 0 || left == 0 {
+                continue;
+            }
+
+            let mut cost = splits as f64 * self.options.split_cost
+                + (right as f64 - left as f64).abs() * self.options.balance_cost;
+            if partition.dx != 0.0 && partition.dy != 0.0 {
+                cost += self.options.diagonal_cost;
+            }
+
+            if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+                best = Some((cost, partition));
+            }
+        }
+
+        return best.map(|(_, partition)| partition);
+    }
+
+    // Box around some segs as top, bottom, left, right like in the NODES lump
+    fn bounding_box(&self, segs: &[BuildSeg]) -> Vec<i16> {
+        let mut top = f64::MIN;
+        let mut bottom = f64::MAX;
+        let mut left = f64::MAX;
+        let mut right = f64::MIN;
+        for seg in segs.iter() {
+            for index in [seg.start, seg.end] {
+                let vertex = &self.vertices[index as usize];
+                top = top.max(vertex.y);
+                bottom = bottom.min(vertex.y);
+                left = left.min(vertex.x);
+                right = right.max(vertex.x);
+            }
+        }
+
+        return vec![
+            top.ceil() as i16,
+            bottom.floor() as i16,
+            left.floor() as i16,
+            right.ceil() as i16,
+        ];
+    }
+
+    fn add_seg(&mut self, seg: BuildSeg) {
+        let start = &self.vertices[seg.start as usize];
+        let end = &self.vertices[seg.end as usize];
+        let offset = seg_offset(
+            start,
+            &self.linedefs[seg.linedef],
+            seg.direction,
+            &self.vertices,
+        );
+
+        self.segs.push(Seg {
+            start: seg.start,
+            end: seg.end,
+            angle: seg_angle(start, end),
+            linedef_num: seg.linedef as i32,
+            direction: seg.direction,
+            offset,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::LineDefFlags;
+    use crate::wad::LumpName;
+
+    // One sided linedefs going around a room clockwise so their front sides face in,
+    // with a sidedef for each wall that faces the room's one sector
+    fn room(points: &[(f64, f64)]) -> (Vec<LineDef>, Vec<SideDef>, Vec<Sector>, Vec<Vertex>) {
+        let vertices: Vec<Vertex> = points
+            .iter()
+            .map(|(x, y)| Vertex { x: *x, y: *y })
+            .collect();
+        let mut linedefs: Vec<LineDef> = Vec::new();
+        for i in 0..points.len() {
+            linedefs.push(LineDef {
+                start: i as u32,
+                end: ((i + 1) % points.len()) as u32,
+                special_type: 0,
+                sector_tag: 0,
+                flags: LineDefFlags::empty(),
+                args: [0; 5],
+                front_sidedef: Some(i as u32),
+                back_sidedef: None,
+            });
+        }
+
+        let sidedefs: Vec<SideDef> = (0..points.len()).map(|_| sidedef(0)).collect();
+        let sectors = vec![sector()];
+
+        return (linedefs, sidedefs, sectors, vertices);
+    }
+
+    fn sector() -> Sector {
+        return Sector {
+            floor_height: 0,
+            ceiling_height: 128,
+            floor_texture: LumpName::new("FLOOR4_8"),
+            ceiling_texture: LumpName::new("CEIL3_5"),
+            light_level: 160,
+            special_type: 0,
+            tag_number: 0,
+        };
+    }
+
+    fn sidedef(facing_sector: u32) -> SideDef {
+        return SideDef {
+            x_offset: 0,
+            y_offset: 0,
+            upper_texture: LumpName::new("-"),
+            lower_texture: LumpName::new("-"),
+            middle_texture: LumpName::new("STARTAN3"),
+            facing_sector,
+        };
+    }
+
+    fn all_vertices(vertices: &[Vertex], built: &ExtendedNodes) -> Vec<Vertex> {
+        let mut all = vertices.to_vec();
+        all.extend(built.new_vertices.iter().copied());
+        return all;
+    }
+
+    fn length(a: &Vertex, b: &Vertex) -> f64 {
+        return ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+    }
+
+    #[test]
+    fn convex_rooms_are_one_subsector() {
+        let (linedefs, sidedefs, sectors, vertices) =
+            room(&[(0.0, 0.0), (0.0, 128.0), (128.0, 128.0), (128.0, 0.0)]);
+        let built = build_nodes(
+            &linedefs,
+            &sidedefs,
+            &sectors,
+            &vertices,
+            &BuildOptions::default(),
+        );
+
+        assert_eq!(built.format, NodeFormat::Built);
+        assert!(built.nodes.is_empty());
+        assert_eq!(built.subsectors.len(), 1);
+        assert_eq!(built.subsectors[0].ssec_size, 4);
+        assert_eq!(built.segs.len(), 4);
+    }
+
+    #[test]
+    fn concave_rooms_get_split_into_convex_subsectors() {
+        // An L shape, the partition along the inside corner has to cut the left wall in two
+        let (linedefs, sidedefs, sectors, vertices) = room(&[
+            (0.0, 0.0),
+            (0.0, 128.0),
+            (64.0, 128.0),
+            (64.0, 64.0),
+            (128.0, 64.0),
+            (128.0, 0.0),
+        ]);
+        let built = build_nodes(
+            &linedefs,
+            &sidedefs,
+            &sectors,
+            &vertices,
+            &BuildOptions::default(),
+        );
+        let vertices = all_vertices(&vertices, &built);
+
+        assert!(!built.nodes.is_empty());
+        assert!(built.subsectors.len() >= 2);
+
+        // Every point of a subsector is on the right of or on each of its segs
+        for ssec in built.subsectors.iter() {
+            let first = ssec.first_seg as usize;
+            let segs = &built.segs[first..first + ssec.ssec_size as usize];
+            for seg in segs.iter() {
+                let (a, b) = (&vertices[seg.start as usize], &vertices[seg.end as usize]);
+                for other in segs.iter() {
+                    for point in [
+                        &vertices[other.start as usize],
+                        &vertices[other.end as usize],
+                    ] {
+                        let cross = (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x);
+                        assert!(cross <= EPSILON);
+                    }
+                }
+            }
+        }
+
+        // Splitting doesn't lose any of the walls
+        for (i, linedef) in linedefs.iter().enumerate() {
+            let covered: f64 = built
+                .segs
+                .iter()
+                .filter(|seg| seg.linedef_num == i as i32)
+                .map(|seg| length(&vertices[seg.start as usize], &vertices[seg.end as usize]))
+                .sum();
+            let full = length(
+                &vertices[linedef.start as usize],
+                &vertices[linedef.end as usize],
+            );
+            assert!((covered - full).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn two_sided_lines_get_a_seg_for_each_side() {
+        let (mut linedefs, mut sidedefs, sectors, vertices) =
+            room(&[(0.0, 0.0), (0.0, 64.0), (64.0, 64.0), (64.0, 0.0)]);
+        linedefs[0].back_sidedef = Some(4);
+        sidedefs.push(sidedef(0));
+
+        let built = build_nodes(
+            &linedefs,
+            &sidedefs,
+            &sectors,
+            &vertices,
+            &BuildOptions::default(),
+        );
+        let on_first: Vec<&Seg> = built
+            .segs
+            .iter()
+            .filter(|seg| seg.linedef_num == 0)
+            .collect();
+        assert_eq!(on_first.len(), 2);
+        assert!(on_first.iter().any(|seg| seg.direction));
+        assert!(on_first.iter().any(|seg| !seg.direction));
+    }
+
+    #[test]
+    fn sides_without_a_sidedef_or_sector_get_no_segs() {
+        let (mut linedefs, mut sidedefs, sectors, vertices) =
+            room(&[(0.0, 0.0), (0.0, 64.0), (64.0, 64.0), (64.0, 0.0)]);
+        linedefs[0].back_sidedef = Some(99);
+        sidedefs[1].facing_sector = 5;
+
+        let built = build_nodes(
+            &linedefs,
+            &sidedefs,
+            &sectors,
+            &vertices,
+            &BuildOptions::default(),
+        );
+        assert_eq!(built.segs.len(), 3);
+        assert!(built.segs.iter().all(|seg| seg.linedef_num != 1));
+    }
+
+    #[test]
+    fn subsectors_start_with_the_sector_most_segs_face() {
+        let (linedefs, mut sidedefs, mut sectors, vertices) =
+            room(&[(0.0, 0.0), (0.0, 64.0), (64.0, 64.0), (64.0, 0.0)]);
+        sectors.push(sector());
+        sidedefs[0].facing_sector = 1;
+
+        let built = build_nodes(
+            &linedefs,
+            &sidedefs,
+            &sectors,
+            &vertices,
+            &BuildOptions::default(),
+        );
+        assert_eq!(built.subsectors.len(), 1);
+        assert_ne!(built.segs[0].linedef_num, 0);
+    }
+}
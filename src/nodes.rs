@@ -0,0 +1,895 @@
+use crate::wad::{LineDef, Node, Seg, SubSector, Vertex, WadError};
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+// The different ways a map's BSP tree can be stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeFormat {
+    Vanilla, // SEGS, SSECTORS and NODES as the original node builders made them
+    Xnod,    // ZDoom extended nodes
+    Znod,    // ZDoom extended nodes compressed with zlib
+    Xgln,    // ZDoom extended GL nodes
+    Zgln,    // ZDoom extended GL nodes compressed with zlib
+    Xgl2,    // ZDoom extended GL nodes with 32 bit linedef indexes
+    Zgl2,    // Compressed version of Xgl2
+    DeePBsp, // DeePBSP's nodes with 32 bit indexes, starting with xNd4 in NODES
+    Gl(u8),  // glBSP's GL_VERT, GL_SEGS, GL_SSECT and GL_NODES lumps, with their version
+    Built,   // Made by our own node builder because the map didn't come with any
+}
+
+impl NodeFormat {
+    // GL nodes have minisegs along the partition lines so every subsector is a closed polygon
+    pub fn has_closed_subsectors(&self) -> bool {
+        return matches!(
+            self,
+            NodeFormat::Xgln
+                | NodeFormat::Zgln
+                | NodeFormat::Xgl2
+                | NodeFormat::Zgl2
+                | NodeFormat::Gl(_)
+        );
+    }
+}
+
+// A seg as it's read before its angle and offset are worked out
+struct RawSeg {
+    start: u32,
+    end: u32,
+    linedef: i32, // -1 for minisegs
+    direction: bool,
+}
+
+// BSP data read from one of the node formats that isn't vanilla
+pub struct ExtendedNodes {
+    pub format: NodeFormat,
+
+    // Vertices made by the node builder when it split segs, they go after the map's own vertices
+    pub new_vertices: Vec<Vertex>,
+
+    pub segs: Vec<Seg>,
+    pub subsectors: Vec<SubSector>,
+    pub nodes: Vec<Node>,
+}
+
+// Reads values one after the other out of a lump
+struct Reader<'a> {
+    lump: &'a str,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    // Makes sure there are enough bytes left and moves past them
+    fn take(&mut self, size: usize) -> Result<&'a [u8], WadError> {
+        if self.pos + size > self.data.len() {
+            return Err(WadError::MalformedLump {
+                name: self.lump.to_string(),
+                offset: self.pos,
+                reason: "nodes end before they should".to_string(),
+            });
+        }
+
+        let bytes = &self.data[self.pos..self.pos + size];
+        self.pos += size;
+        return Ok(bytes);
+    }
+
+    fn u8(&mut self) -> Result<u8, WadError> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn i16(&mut self) -> Result<i16, WadError> {
+        return Ok(<LittleEndian as ByteOrder>::read_i16(self.take(2)?));
+    }
+
+    fn u16(&mut self) -> Result<u16, WadError> {
+        return Ok(<LittleEndian as ByteOrder>::read_u16(self.take(2)?));
+    }
+
+    fn i32(&mut self) -> Result<i32, WadError> {
+        return Ok(<LittleEndian as ByteOrder>::read_i32(self.take(4)?));
+    }
+
+    fn u32(&mut self) -> Result<u32, WadError> {
+        return Ok(<LittleEndian as ByteOrder>::read_u32(self.take(4)?));
+    }
+
+    // Reads a count and makes sure there's at least enough data left for that many records,
+    // so a garbage count can't make it try to allocate a huge vector
+    fn count(&mut self, record_size: usize) -> Result<usize, WadError> {
+        let offset = self.pos;
+        let count = self.u32()? as usize;
+
+        if count.saturating_mul(record_size) > self.data.len() - self.pos {
+            return Err(WadError::MalformedLump {
+                name: self.lump.to_string(),
+                offset,
+                reason: format!(
+                    "says there are {} records but there isn't room for them",
+                    count
+                ),
+            });
+        }
+
+        return Ok(count);
+    }
+
+    fn error(&self, reason: String) -> WadError {
+        return WadError::MalformedLump {
+            name: self.lump.to_string(),
+            offset: self.pos,
+            reason,
+        };
+    }
+}
+
+// Reads a vertex stored as 16.16 fixed point numbers
+fn read_fixed_vertex(reader: &mut Reader) -> Result<Vertex, WadError> {
+    let x = reader.i32()? as f64 / 65536.0;
+    let y = reader.i32()? as f64 / 65536.0;
+
+    return Ok(Vertex { x, y });
+}
+
+// Reads a node, which only changes between formats in how big the child indexes are
+fn read_node(reader: &mut Reader, wide_children: bool) -> Result<Node, WadError> {
+    let start = vec![reader.i16()?, reader.i16()?];
+    let change = vec![reader.i16()?, reader.i16()?];
+    let right_box = vec![reader.i16()?, reader.i16()?, reader.i16()?, reader.i16()?];
+    let left_box = vec![reader.i16()?, reader.i16()?, reader.i16()?, reader.i16()?];
+
+    // The top bit says if the child is a subsector and the rest is the index
+    let (right_child, left_child, ssec_bit) = if wide_children {
+        (reader.u32()?, reader.u32()?, 0x80000000)
+    } else {
+        (reader.u16()? as u32, reader.u16()? as u32, 0x8000)
+    };
+
+    return Ok(Node {
+        start,
+        change,
+        right_box,
+        left_box,
+        right_is_ssec: right_child & ssec_bit != 0,
+        right_index: right_child & (ssec_bit - 1),
+        left_is_ssec: left_child & ssec_bit != 0,
+        left_index: left_child & (ssec_bit - 1),
+    });
+}
+
+// Turns raw segs into real ones by checking their indexes and working out their angles and offsets
+// Segs in closed subsectors don't store their end vertex since it's where the next seg starts
+fn finish_segs(
+    reader: &Reader,
+    mut raw_segs: Vec<RawSeg>,
+    subsectors: &[SubSector],
+    closed: bool,
+    vertices: &[Vertex],
+    linedefs: &[LineDef],
+) -> Result<Vec<Seg>, WadError> {
+    if closed {
+        for ssec in subsectors.iter() {
+            let first = ssec.first_seg as usize;
+            let last = first + ssec.ssec_size as usize;
+            if last > raw_segs.len() {
+                return Err(reader.error(format!(
+                    "subsector uses segs {} to {} but there are only {}",
+                    first,
+                    last,
+                    raw_segs.len()
+                )));
+            }
+            for i in first..last {
+                let next = if i + 1 < last { i + 1 } else { first };
+                raw_segs[i].end = raw_segs[next].start;
+            }
+        }
+    }
+
+    let mut segs: Vec<Seg> = Vec::new();
+    for raw in raw_segs {
+        if raw.start as usize >= vertices.len() || raw.end as usize >= vertices.len() {
+            return Err(reader.error(format!(
+                "seg uses vertices {} and {} but there are only {}",
+                raw.start,
+                raw.end,
+                vertices.len()
+            )));
+        }
+        if raw.linedef != -1 && raw.linedef as usize >= linedefs.len() {
+            return Err(reader.error(format!("linedef index {} is out of range", raw.linedef)));
+        }
+
+        let start = &vertices[raw.start as usize];
+        let end = &vertices[raw.end as usize];
+
+        // Offsets are measured from the linedef's vertices which are always the map's own ones
+        let offset = if raw.linedef == -1 {
+            0
+        } else {
+            seg_offset(
+                start,
+                &linedefs[raw.linedef as usize],
+                raw.direction,
+                vertices,
+            )
+        };
+
+        segs.push(Seg {
+            start: raw.start,
+            end: raw.end,
+            angle: seg_angle(start, end),
+            linedef_num: raw.linedef,
+            direction: raw.direction,
+            offset,
+        });
+    }
+
+    return Ok(segs);
+}
+
+// Angle of a seg in degrees from its vertices
+pub(crate) fn seg_angle(start: &Vertex, end: &Vertex) -> f64 {
+    return (end.y - start.y).atan2(end.x - start.x).to_degrees();
+}
+
+// Distance from the start of the linedef (or the end if it's on the back side) to the start of the seg
+pub(crate) fn seg_offset(
+    start: &Vertex,
+    linedef: &LineDef,
+    direction: bool,
+    vertices: &[Vertex],
+) -> i16 {
+    let line_vertex = if direction {
+        linedef.end
+    } else {
+        linedef.start
+    };
+    match vertices.get(line_vertex as usize) {
+        Some(from) => ((start.x - from.x).powi(2) + (start.y - from.y).powi(2))
+            .sqrt()
+            .round() as i16,
+        None => 0,
+    }
+}
+
+// Reads a NODES or ZNODES lump if it's in one of ZDoom's extended formats
+// Returns None for vanilla nodes so they can be read the normal way
+pub fn read_extended_nodes(
+    lump: &str,
+    data: &[u8],
+    vertices: &[Vertex],
+    linedefs: &[LineDef],
+) -> Result<Option<ExtendedNodes>, WadError> {
+    if data.len() < 4 {
+        return Ok(None);
+    }
+
+    let format = match &data[0..4] {
+        b"XNOD" => NodeFormat::Xnod,
+        b"ZNOD" => NodeFormat::Znod,
+        b"XGLN" => NodeFormat::Xgln,
+        b"ZGLN" => NodeFormat::Zgln,
+        b"XGL2" => NodeFormat::Xgl2,
+        b"ZGL2" => NodeFormat::Zgl2,
+        _ => return Ok(None),
+    };
+
+    // The compressed versions are the same thing after the signature is run through zlib
+    let decompressed: Vec<u8>;
+    let body: &[u8] = match format {
+        NodeFormat::Znod | NodeFormat::Zgln | NodeFormat::Zgl2 => {
+            let mut decoder = ZlibDecoder::new(&data[4..]);
+            let mut bytes: Vec<u8> = Vec::new();
+            if let Err(err) = decoder.read_to_end(&mut bytes) {
+                return Err(WadError::MalformedLump {
+                    name: lump.to_string(),
+                    offset: 4,
+                    reason: format!("couldn't decompress nodes: {}", err),
+                });
+            }
+            decompressed = bytes;
+            &decompressed
+        }
+        _ => &data[4..],
+    };
+
+    let mut reader = Reader {
+        lump,
+        data: body,
+        pos: 0,
+    };
+
+    // Vertices
+    let org_verts = reader.u32()? as usize;
+    let num_new_verts = reader.count(8)?;
+    let mut new_vertices: Vec<Vertex> = Vec::new();
+    for _ in 0..num_new_verts {
+        new_vertices.push(read_fixed_vertex(&mut reader)?);
+    }
+
+    // The nodes number new vertices starting right after the ones the map had when they were built,
+    // which might not be how many the map has now
+    if org_verts > vertices.len() {
+        return Err(reader.error(format!(
+            "built for {} vertices but the map only has {}",
+            org_verts,
+            vertices.len()
+        )));
+    }
+    let vertex_index = |index: u32| -> u32 {
+        if (index as usize) < org_verts {
+            index
+        } else {
+            (vertices.len() + (index as usize - org_verts)) as u32
+        }
+    };
+
+    // Subsectors only store how many segs they have, the segs are all in order
+    let num_subsectors = reader.count(4)?;
+    let mut subsectors: Vec<SubSector> = Vec::new();
+    let mut first_seg: u32 = 0;
+    for _ in 0..num_subsectors {
+        let ssec_size = reader.u32()?;
+        subsectors.push(SubSector {
+            ssec_size,
+            first_seg,
+        });
+        first_seg = first_seg.saturating_add(ssec_size);
+    }
+
+    // Segs
+    let is_gl = !matches!(format, NodeFormat::Xnod | NodeFormat::Znod);
+    let seg_size = match format {
+        NodeFormat::Xgl2 | NodeFormat::Zgl2 => 13,
+        _ => 11,
+    };
+    let num_segs = reader.count(seg_size)?;
+    if first_seg as usize != num_segs {
+        return Err(reader.error(format!(
+            "subsectors use {} segs but there are {}",
+            first_seg, num_segs
+        )));
+    }
+
+    let mut raw_segs: Vec<RawSeg> = Vec::new();
+    for _ in 0..num_segs {
+        let start = vertex_index(reader.u32()?);
+
+        // GL segs have the seg on the other side of the line here instead of the end vertex
+        let end = reader.u32()?;
+        let end = if is_gl { 0 } else { vertex_index(end) };
+
+        let linedef = match format {
+            NodeFormat::Xgl2 | NodeFormat::Zgl2 => {
+                let linedef = reader.u32()?;
+                if linedef == 0xFFFFFFFF {
+                    -1
+                } else {
+                    linedef as i32
+                }
+            }
+            _ => {
+                let linedef = reader.u16()?;
+                if linedef == 0xFFFF {
+                    -1
+                } else {
+                    linedef as i32
+                }
+            }
+        };
+
+        let side = reader.u8()?;
+        raw_segs.push(RawSeg {
+            start,
+            end,
+            linedef,
+            direction: side == 1,
+        });
+    }
+
+    let mut all_vertices = vertices.to_vec();
+    all_vertices.extend(new_vertices.iter().copied());
+    let segs = finish_segs(
+        &reader,
+        raw_segs,
+        &subsectors,
+        is_gl,
+        &all_vertices,
+        linedefs,
+    )?;
+
+    // Nodes are the same as vanilla apart from the 32 bit child indexes
+    let num_nodes = reader.count(32)?;
+    let mut nodes: Vec<Node> = Vec::new();
+    for _ in 0..num_nodes {
+        nodes.push(read_node(&mut reader, true)?);
+    }
+
+    return Ok(Some(ExtendedNodes {
+        format,
+        new_vertices,
+        segs,
+        subsectors,
+        nodes,
+    }));
+}
+
+// Reads DeePBSP's nodes, which are vanilla nodes with every index made 32 bits
+// NODES starts with xNd4 and split vertices are just added to VERTEXES like vanilla
+// Returns None if the nodes aren't in this format
+pub fn read_deepbsp_nodes(
+    segs_data: &[u8],
+    ssectors_data: &[u8],
+    nodes_data: &[u8],
+    vertices: &[Vertex],
+    linedefs: &[LineDef],
+) -> Result<Option<ExtendedNodes>, WadError> {
+    if nodes_data.len() < 8 || &nodes_data[0..8] != b"xNd4\0\0\0\0" {
+        return Ok(None);
+    }
+
+    // Subsectors
+    let mut reader = Reader {
+        lump: "SSECTORS",
+        data: ssectors_data,
+        pos: 0,
+    };
+    // The seg count stays 16 bits, only the first seg is made bigger
+    let mut subsectors: Vec<SubSector> = Vec::new();
+    for _ in 0..(ssectors_data.len() / 6) {
+        let ssec_size = reader.u16()? as u32;
+        let first_seg = reader.u32()?;
+        subsectors.push(SubSector {
+            ssec_size,
+            first_seg,
+        });
+    }
+
+    // Segs
+    let mut reader = Reader {
+        lump: "SEGS",
+        data: segs_data,
+        pos: 0,
+    };
+    let mut raw_segs: Vec<RawSeg> = Vec::new();
+    for _ in 0..(segs_data.len() / 16) {
+        let start = reader.u32()?;
+        let end = reader.u32()?;
+
+        // Angle and offset get worked out again from the vertices
+        reader.u16()?;
+        let linedef = reader.u16()?;
+        let side = reader.u16()?;
+        reader.u16()?;
+
+        raw_segs.push(RawSeg {
+            start,
+            end,
+            linedef: if linedef == 0xFFFF {
+                -1
+            } else {
+                linedef as i32
+            },
+            direction: side == 1,
+        });
+    }
+    let segs = finish_segs(&reader, raw_segs, &subsectors, false, vertices, linedefs)?;
+
+    // Nodes
+    let mut reader = Reader {
+        lump: "NODES",
+        data: nodes_data,
+        pos: 8,
+    };
+    let mut nodes: Vec<Node> = Vec::new();
+    for _ in 0..((nodes_data.len() - 8) / 32) {
+        nodes.push(read_node(&mut reader, true)?);
+    }
+
+    return Ok(Some(ExtendedNodes {
+        format: NodeFormat::DeePBsp,
+        new_vertices: Vec::new(),
+        segs,
+        subsectors,
+        nodes,
+    }));
+}
+
+// Reads the GL nodes glBSP puts in a GL_ marker after the map (GL_E1M1 for E1M1)
+// GL nodes split the map all the way down to convex polygons and have their own list of vertices,
+// seg vertex indexes with the GL bit set point into that list instead of the map's
+// Returns None if there are no GL nodes or they're a version that isn't supported
+pub fn read_gl_nodes(
+    vert_data: &[u8],
+    segs_data: &[u8],
+    ssect_data: &[u8],
+    nodes_data: &[u8],
+    vertices: &[Vertex],
+    linedefs: &[LineDef],
+) -> Result<Option<ExtendedNodes>, WadError> {
+    if vert_data.is_empty() || segs_data.is_empty() || ssect_data.is_empty() {
+        return Ok(None);
+    }
+
+    // The version is in GL_VERT, version 1 doesn't have a signature at all
+    let version: u8 = match vert_data.get(0..4) {
+        Some(b"gNd2") => 2,
+        Some(b"gNd3") => 3,
+        Some(b"gNd5") => 5,
+        Some(magic) if &magic[0..3] == b"gNd" => return Ok(None),
+        _ => 1,
+    };
+
+    // Vertices, fixed point in every version but the first
+    let mut reader = Reader {
+        lump: "GL_VERT",
+        data: vert_data,
+        pos: if version == 1 { 0 } else { 4 },
+    };
+    let mut new_vertices: Vec<Vertex> = Vec::new();
+    if version == 1 {
+        for _ in 0..(vert_data.len() / 4) {
+            let x = reader.i16()? as f64;
+            let y = reader.i16()? as f64;
+            new_vertices.push(Vertex { x, y });
+        }
+    } else {
+        for _ in 0..((vert_data.len() - 4) / 8) {
+            new_vertices.push(read_fixed_vertex(&mut reader)?);
+        }
+    }
+
+    // Version 3 marks the segs and subsectors it changed with their own signature
+    let wide_segs = version == 5 || segs_data.starts_with(b"gNd3");
+    let wide_ssects = version == 5 || ssect_data.starts_with(b"gNd3");
+
+    // Subsectors
+    let mut reader = Reader {
+        lump: "GL_SSECT",
+        data: ssect_data,
+        pos: if ssect_data.starts_with(b"gNd3") {
+            4
+        } else {
+            0
+        },
+    };
+    let mut subsectors: Vec<SubSector> = Vec::new();
+    if wide_ssects {
+        for _ in 0..((ssect_data.len() - reader.pos) / 8) {
+            let ssec_size = reader.u32()?;
+            let first_seg = reader.u32()?;
+            subsectors.push(SubSector {
+                ssec_size,
+                first_seg,
+            });
+        }
+    } else {
+        for _ in 0..(ssect_data.len() / 4) {
+            let ssec_size = reader.u16()? as u32;
+            let first_seg = reader.u16()? as u32;
+            subsectors.push(SubSector {
+                ssec_size,
+                first_seg,
+            });
+        }
+    }
+
+    // Segs, which bit means "GL vertex" depends on the version
+    let gl_bit: u32 = if !wide_segs {
+        0x8000
+    } else if version == 5 {
+        0x80000000
+    } else {
+        0x40000000
+    };
+    let vertex_index = |index: u32| -> u32 {
+        if index & gl_bit != 0 {
+            (vertices.len() as u32).saturating_add(index & !gl_bit)
+        } else {
+            index
+        }
+    };
+
+    let mut reader = Reader {
+        lump: "GL_SEGS",
+        data: segs_data,
+        pos: if segs_data.starts_with(b"gNd3") { 4 } else { 0 },
+    };
+    let mut raw_segs: Vec<RawSeg> = Vec::new();
+    let seg_size = if wide_segs { 16 } else { 10 };
+    for _ in 0..((segs_data.len() - reader.pos) / seg_size) {
+        let (start, end) = if wide_segs {
+            (reader.u32()?, reader.u32()?)
+        } else {
+            (reader.u16()? as u32, reader.u16()? as u32)
+        };
+        let linedef = reader.u16()?;
+        let side = reader.u16()?;
+
+        // The seg on the other side of the line isn't needed
+        if wide_segs {
+            reader.u32()?;
+        } else {
+            reader.u16()?;
+        }
+
+        raw_segs.push(RawSeg {
+            start: vertex_index(start),
+            end: vertex_index(end),
+            linedef: if linedef == 0xFFFF {
+                -1
+            } else {
+                linedef as i32
+            },
+            direction: side == 1,
+        });
+    }
+
+    let mut all_vertices = vertices.to_vec();
+    all_vertices.extend(new_vertices.iter().copied());
+    let segs = finish_segs(
+        &reader,
+        raw_segs,
+        &subsectors,
+        false,
+        &all_vertices,
+        linedefs,
+    )?;
+
+    // Nodes only got 32 bit children in version 5
+    let mut reader = Reader {
+        lump: "GL_NODES",
+        data: nodes_data,
+        pos: 0,
+    };
+    let mut nodes: Vec<Node> = Vec::new();
+    let node_size = if version == 5 { 32 } else { 28 };
+    for _ in 0..(nodes_data.len() / node_size) {
+        nodes.push(read_node(&mut reader, version == 5)?);
+    }
+
+    return Ok(Some(ExtendedNodes {
+        format: NodeFormat::Gl(version),
+        new_vertices,
+        segs,
+        subsectors,
+        nodes,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::LineDefFlags;
+
+    fn test_vertices() -> Vec<Vertex> {
+        return vec![
+            Vertex { x: 0.0, y: 0.0 },
+            Vertex { x: 64.0, y: 0.0 },
+            Vertex { x: 64.0, y: 64.0 },
+        ];
+    }
+
+    fn test_linedefs() -> Vec<LineDef> {
+        let linedef = |start: u32, end: u32| LineDef {
+            start,
+            end,
+            special_type: 0,
+            sector_tag: 0,
+            flags: LineDefFlags::empty(),
+            args: [0; 5],
+            front_sidedef: Some(0),
+            back_sidedef: None,
+        };
+        return vec![linedef(0, 1), linedef(1, 2)];
+    }
+
+    fn push_u16(data: &mut Vec<u8>, value: u16) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn push_u32(data: &mut Vec<u8>, value: u32) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn deepbsp_subsectors_are_6_bytes() {
+        // Two segs, one along each linedef
+        let mut segs: Vec<u8> = Vec::new();
+        for (start, end, linedef) in [(0, 1, 0), (1, 2, 1)] {
+            push_u32(&mut segs, start);
+            push_u32(&mut segs, end);
+            push_u16(&mut segs, 0);
+            push_u16(&mut segs, linedef);
+            push_u16(&mut segs, 0);
+            push_u16(&mut segs, 0);
+        }
+
+        // Two subsectors with one seg each, a 16 bit count then a 32 bit first seg
+        let mut ssectors: Vec<u8> = Vec::new();
+        push_u16(&mut ssectors, 1);
+        push_u32(&mut ssectors, 0);
+        push_u16(&mut ssectors, 1);
+        push_u32(&mut ssectors, 1);
+
+        // One node splitting along x = 64 with both children being subsectors
+        let mut nodes: Vec<u8> = b"xNd4\0\0\0\0".to_vec();
+        for value in [64i16, 0, 0, 64, 64, 0, 64, 64, 64, 0, 0, 64] {
+            push_u16(&mut nodes, value as u16);
+        }
+        push_u32(&mut nodes, 0x80000000);
+        push_u32(&mut nodes, 0x80000001);
+
+        let extended =
+            read_deepbsp_nodes(&segs, &ssectors, &nodes, &test_vertices(), &test_linedefs())
+                .unwrap()
+                .unwrap();
+
+        assert_eq!(extended.subsectors.len(), 2);
+        assert_eq!(extended.subsectors[1].ssec_size, 1);
+        assert_eq!(extended.subsectors[1].first_seg, 1);
+        assert_eq!(extended.segs.len(), 2);
+        assert_eq!(extended.segs[1].linedef_num, 1);
+        assert_eq!(extended.nodes.len(), 1);
+        assert!(extended.nodes[0].left_is_ssec);
+        assert_eq!(extended.nodes[0].left_index, 1);
+    }
+
+    #[test]
+    fn deepbsp_needs_its_signature() {
+        let nodes = vec![0u8; 32];
+        let result = read_deepbsp_nodes(&[], &[], &nodes, &test_vertices(), &test_linedefs());
+        assert!(result.unwrap().is_none());
+    }
+
+    // XNOD body with one split vertex at (32, 0) cutting the first linedef in two
+    fn xnod_body() -> Vec<u8> {
+        let mut body: Vec<u8> = Vec::new();
+        push_u32(&mut body, 3);
+        push_u32(&mut body, 1);
+        push_u32(&mut body, 32 << 16);
+        push_u32(&mut body, 0);
+
+        push_u32(&mut body, 2);
+        push_u32(&mut body, 1);
+        push_u32(&mut body, 1);
+
+        push_u32(&mut body, 2);
+        for (start, end) in [(0, 3), (3, 1)] {
+            push_u32(&mut body, start);
+            push_u32(&mut body, end);
+            push_u16(&mut body, 0);
+            body.push(0);
+        }
+
+        push_u32(&mut body, 1);
+        for value in [32i16, 0, 0, 64, 64, 0, 32, 64, 64, 0, 0, 32] {
+            push_u16(&mut body, value as u16);
+        }
+        push_u32(&mut body, 0x80000000);
+        push_u32(&mut body, 0x80000001);
+
+        return body;
+    }
+
+    #[test]
+    fn xnod_split_vertices_go_after_the_map_ones() {
+        let mut data = b"XNOD".to_vec();
+        data.extend(xnod_body());
+
+        let extended = read_extended_nodes("NODES", &data, &test_vertices(), &test_linedefs())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(extended.format, NodeFormat::Xnod);
+        assert_eq!(extended.new_vertices, vec![Vertex { x: 32.0, y: 0.0 }]);
+        assert_eq!(extended.segs[0].end, 3);
+        assert_eq!(extended.segs[1].start, 3);
+        assert_eq!(extended.segs[1].offset, 32);
+        assert_eq!(extended.subsectors[1].first_seg, 1);
+        assert_eq!(extended.nodes[0].right_index, 0);
+        assert!(extended.nodes[0].right_is_ssec);
+    }
+
+    #[test]
+    fn znod_is_compressed_xnod() {
+        use flate2::write::ZlibEncoder;
+        use flate2::Compression;
+        use std::io::Write;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&xnod_body()).unwrap();
+        let mut data = b"ZNOD".to_vec();
+        data.extend(encoder.finish().unwrap());
+
+        let extended = read_extended_nodes("ZNODES", &data, &test_vertices(), &test_linedefs())
+            .unwrap()
+            .unwrap();
+        assert_eq!(extended.format, NodeFormat::Znod);
+        assert_eq!(extended.segs.len(), 2);
+        assert_eq!(extended.segs[1].offset, 32);
+    }
+
+    #[test]
+    fn cut_off_extended_nodes_are_errors() {
+        let mut data = b"XNOD".to_vec();
+        data.extend(xnod_body());
+        data.truncate(40);
+
+        let result = read_extended_nodes("NODES", &data, &test_vertices(), &test_linedefs());
+        assert!(matches!(result, Err(WadError::MalformedLump { .. })));
+    }
+
+    #[test]
+    fn vanilla_nodes_are_left_alone() {
+        let result = read_extended_nodes("NODES", &[0u8; 28], &test_vertices(), &test_linedefs());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn gl_segs_use_the_gl_vertex_bit() {
+        let mut gl_vert = b"gNd2".to_vec();
+        push_u32(&mut gl_vert, 32 << 16);
+        push_u32(&mut gl_vert, 0);
+
+        // The second subsector is closed off by a miniseg that isn't on any linedef
+        let mut gl_segs: Vec<u8> = Vec::new();
+        for (start, end, linedef) in [(0, 0x8000, 0), (0x8000, 1, 0), (1, 0x8000, 0xFFFF)] {
+            push_u16(&mut gl_segs, start);
+            push_u16(&mut gl_segs, end);
+            push_u16(&mut gl_segs, linedef);
+            push_u16(&mut gl_segs, 0);
+            push_u16(&mut gl_segs, 0xFFFF);
+        }
+
+        let mut gl_ssect: Vec<u8> = Vec::new();
+        for (count, first) in [(1, 0), (2, 1)] {
+            push_u16(&mut gl_ssect, count);
+            push_u16(&mut gl_ssect, first);
+        }
+
+        let mut gl_nodes: Vec<u8> = Vec::new();
+        for value in [32i16, 0, 0, 64, 64, 0, 32, 64, 64, 0, 0, 32] {
+            push_u16(&mut gl_nodes, value as u16);
+        }
+        push_u16(&mut gl_nodes, 0x8000);
+        push_u16(&mut gl_nodes, 0x8001);
+
+        let extended = read_gl_nodes(
+            &gl_vert,
+            &gl_segs,
+            &gl_ssect,
+            &gl_nodes,
+            &test_vertices(),
+            &test_linedefs(),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(extended.format, NodeFormat::Gl(2));
+        assert_eq!(extended.new_vertices, vec![Vertex { x: 32.0, y: 0.0 }]);
+        assert_eq!(extended.segs[0].end, 3);
+        assert_eq!(extended.segs[1].start, 3);
+        assert_eq!(extended.segs[1].offset, 32);
+        assert_eq!(extended.segs[2].linedef_num, -1);
+        assert_eq!(extended.subsectors[1].ssec_size, 2);
+        assert!(extended.nodes[0].left_is_ssec);
+        assert_eq!(extended.nodes[0].left_index, 1);
+    }
+
+    #[test]
+    fn unknown_gl_versions_are_skipped() {
+        let result = read_gl_nodes(
+            b"gNd9",
+            &[0u8; 10],
+            &[0u8; 4],
+            &[],
+            &test_vertices(),
+            &test_linedefs(),
+        );
+        assert!(result.unwrap().is_none());
+    }
+}
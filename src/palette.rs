@@ -0,0 +1,161 @@
+use crate::wad::WadError;
+use crate::wad_stack::WadStack;
+
+// Everything in Doom is drawn with 256 colour indexes instead of real colours
+// PLAYPAL turns an index into a colour and COLORMAP darkens an index into another index
+
+// Doom has 14 palettes, 0 is the normal one and the rest tint the screen red, gold or green
+// for pain, picking things up and the radiation suit
+pub const NUM_PALETTES: usize = 14;
+
+// 32 light levels from brightest to darkest, then the invulnerability map, then an all black one
+pub const NUM_COLORMAPS: usize = 34;
+pub const NUM_LIGHT_LEVELS: usize = 32;
+pub const INVULNERABILITY_MAP: usize = 32;
+
+// Sector light levels get cut down to 16 steps before they're used
+const LIGHT_SEG_SHIFT: i32 = 4;
+
+// How far away each step of distance fading is in map units
+const LIGHT_Z_STEP: f64 = 16.0;
+const MAX_LIGHT_Z: i32 = 128;
+
+pub struct Palette {
+    // Red, green and blue for each of the 256 indexes in each palette
+    palettes: Vec<[[u8; 3]; 256]>,
+}
+
+pub struct Colormap {
+    // Which index each of the 256 indexes turns into in each map
+    maps: Vec<[u8; 256]>,
+}
+
+impl Palette {
+    // Reads the PLAYPAL lump, other games have a different number of palettes so any amount is fine
+    pub fn from_bytes(data: &[u8]) -> Result<Palette, WadError> {
+        if data.len() < 768 {
+            return Err(WadError::MalformedLump {
+                name: "PLAYPAL".to_string(),
+                offset: 0,
+                reason: format!("{} bytes is too small for one palette", data.len()),
+            });
+        }
+
+        let mut palettes: Vec<[[u8; 3]; 256]> = Vec::new();
+        for i in 0..(data.len() / 768) {
+            let palette_loc: usize = i * 768;
+
+            let mut colors = [[0u8; 3]; 256];
+            for (j, color) in colors.iter_mut().enumerate() {
+                let color_loc = palette_loc + j * 3;
+                color.copy_from_slice(&data[color_loc..color_loc + 3]);
+            }
+            palettes.push(colors);
+        }
+
+        return Ok(Palette { palettes });
+    }
+
+    // Gets PLAYPAL from the last file that has it
+    pub fn load(wads: &WadStack) -> Result<Palette, WadError> {
+        match wads.lump_data("PLAYPAL") {
+            Some(data) => Palette::from_bytes(data),
+            None => Err(WadError::MissingLump("PLAYPAL".to_string())),
+        }
+    }
+
+    pub fn num_palettes(&self) -> usize {
+        return self.palettes.len();
+    }
+
+    // RGBA colour of an index in one of the palettes, palettes that don't exist use the normal one
+    pub fn color(&self, palette: usize, index: u8) -> [u8; 4] {
+        let colors = self.palettes.get(palette).unwrap_or(&self.palettes[0]);
+        let [r, g, b] = colors[index as usize];
+
+        return [r, g, b, 255];
+    }
+
+    // Colour of an index in the normal palette after it's been darkened for a sector's light level
+    // and how far away it is
+    pub fn lit_color(
+        &self,
+        colormap: &Colormap,
+        index: u8,
+        light_level: i16,
+        distance: f64,
+    ) -> [u8; 4] {
+        return self.color(0, colormap.shade(index, light_level, distance));
+    }
+}
+
+impl Colormap {
+    // Reads the COLORMAP lump, it needs at least all of the light levels
+    // Anything after the last whole map gets ignored since some editors pad the lump
+    pub fn from_bytes(data: &[u8]) -> Result<Colormap, WadError> {
+        if data.len() < NUM_LIGHT_LEVELS * 256 {
+            return Err(WadError::MalformedLump {
+                name: "COLORMAP".to_string(),
+                offset: 0,
+                reason: format!(
+                    "{} bytes is too small for {} light levels",
+                    data.len(),
+                    NUM_LIGHT_LEVELS
+                ),
+            });
+        }
+
+        let mut maps: Vec<[u8; 256]> = Vec::new();
+        for i in 0..(data.len() / 256) {
+            let map_loc: usize = i * 256;
+
+            let mut map = [0u8; 256];
+            map.copy_from_slice(&data[map_loc..map_loc + 256]);
+            maps.push(map);
+        }
+
+        return Ok(Colormap { maps });
+    }
+
+    // Gets COLORMAP from the last file that has it
+    pub fn load(wads: &WadStack) -> Result<Colormap, WadError> {
+        match wads.lump_data("COLORMAP") {
+            Some(data) => Colormap::from_bytes(data),
+            None => Err(WadError::MissingLump("COLORMAP".to_string())),
+        }
+    }
+
+    pub fn num_maps(&self) -> usize {
+        return self.maps.len();
+    }
+
+    // Which map to use for a sector's light level at a distance, 0 is brightest and 31 is darkest
+    // Works the same way as the zlight table in the original renderer with the screen 320 wide
+    pub fn light_map(light_level: i16, distance: f64) -> usize {
+        let light = (light_level.clamp(0, 255) as i32) >> LIGHT_SEG_SHIFT;
+        let start_map = (15 - light) * 4;
+
+        let z = ((distance.max(0.0) / LIGHT_Z_STEP) as i32).min(MAX_LIGHT_Z - 1);
+        let scale = 160 / (z + 1);
+
+        return (start_map - scale / 2).clamp(0, NUM_LIGHT_LEVELS as i32 - 1) as usize;
+    }
+
+    // Index after it's been darkened for a light level and distance
+    pub fn shade(&self, index: u8, light_level: i16, distance: f64) -> u8 {
+        return self.maps[Colormap::light_map(light_level, distance)][index as usize];
+    }
+
+    // Index in the whitish invulnerability colours, just the normal index if the map is missing
+    pub fn invulnerability(&self, index: u8) -> u8 {
+        match self.maps.get(INVULNERABILITY_MAP) {
+            Some(map) => map[index as usize],
+            None => index,
+        }
+    }
+
+    // One of the maps by its number, None if the lump doesn't have it
+    pub fn map(&self, map: usize) -> Option<&[u8; 256]> {
+        return self.maps.get(map);
+    }
+}
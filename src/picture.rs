@@ -0,0 +1,312 @@
+use crate::palette::Palette;
+use crate::wad::WadError;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use macroquad::prelude::{FilterMode, Texture2D};
+
+// Anything bigger than this is a broken header rather than a real picture
+const MAX_PIXELS: usize = 4096 * 4096;
+
+// A run of solid pixels in a column, as it's stored in the lump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Post {
+    pub top: usize, // Rows down from the top of the picture, tall patch tops already worked out
+    pub pixels: Vec<u8>,
+}
+
+// Doom's picture format, used for wall patches, sprites, menus, the status bar and TITLEPIC
+// Pictures are stored as columns, each made of posts of solid pixels with gaps between them
+// that are see-through
+pub struct Picture {
+    pub width: usize,
+    pub height: usize,
+
+    // How far the picture is drawn left and up from where it's placed
+    // Sprites use these to stand on the ground, wall patches mostly ignore them
+    pub left_offset: i16,
+    pub top_offset: i16,
+
+    // Palette index of each pixel going across then down, None where it's see-through
+    pixels: Vec<Option<u8>>,
+
+    // The posts in each column, which can go past the bottom of the picture
+    // Wall textures are built from these since the engine draws the part past the bottom too
+    posts: Vec<Vec<Post>>,
+}
+
+impl Picture {
+    // Decodes a picture lump, the name is only used for errors
+    pub fn from_bytes(name: &str, data: &[u8]) -> Result<Picture, WadError> {
+        let error = |offset: usize, reason: String| WadError::MalformedLump {
+            name: name.to_string(),
+            offset,
+            reason,
+        };
+
+        if data.len() < 8 {
+            return Err(error(0, "too small to have a header".to_string()));
+        }
+
+        let width = <LittleEndian as ByteOrder>::read_u16(&data[0..2]) as usize;
+        let height = <LittleEndian as ByteOrder>::read_u16(&data[2..4]) as usize;
+        let left_offset = <LittleEndian as ByteOrder>::read_i16(&data[4..6]);
+        let top_offset = <LittleEndian as ByteOrder>::read_i16(&data[6..8]);
+
+        if 8 + width * 4 > data.len() {
+            return Err(error(8, format!("offsets for {} columns don't fit", width)));
+        }
+        if width * height > MAX_PIXELS {
+            return Err(error(
+                0,
+                format!("{} by {} is too big for a picture", width, height),
+            ));
+        }
+
+        let mut pixels: Vec<Option<u8>> = vec![None; width * height];
+        let mut posts: Vec<Vec<Post>> = Vec::new();
+
+        for x in 0..width {
+            let mut column: Vec<Post> = Vec::new();
+            let offset_loc: usize = 8 + x * 4;
+            let mut post_loc =
+                <LittleEndian as ByteOrder>::read_u32(&data[offset_loc..offset_loc + 4]) as usize;
+
+            // Tall patches go over 255 pixels by making each top that isn't lower than the last one
+            // count from the last one instead of from the top of the picture
+            let mut last_top: i64 = -1;
+
+            loop {
+                if post_loc >= data.len() {
+                    return Err(error(post_loc, format!("column {} runs past the end", x)));
+                }
+
+                // 0xFF ends the column
+                let top_delta = data[post_loc] as i64;
+                if top_delta == 0xFF {
+                    break;
+                }
+
+                if post_loc + 3 > data.len() {
+                    return Err(error(post_loc, format!("post in column {} is cut off", x)));
+                }
+                let length = data[post_loc + 1] as usize;
+
+                // There's an unused byte before and after the pixels
+                let pixels_loc = post_loc + 3;
+                if pixels_loc + length > data.len() {
+                    return Err(error(
+                        post_loc,
+                        format!("post in column {} goes past the end", x),
+                    ));
+                }
+
+                let top = if top_delta <= last_top {
+                    last_top + top_delta
+                } else {
+                    top_delta
+                };
+                last_top = top;
+
+                // Posts that hang off the bottom just get cut off like in the game
+                for i in 0..length {
+                    let y = top as usize + i;
+                    if y >= height {
+                        break;
+                    }
+                    pixels[y * width + x] = Some(data[pixels_loc + i]);
+                }
+                column.push(Post {
+                    top: top as usize,
+                    pixels: data[pixels_loc..pixels_loc + length].to_vec(),
+                });
+
+                post_loc = pixels_loc + length + 1;
+            }
+            posts.push(column);
+        }
+
+        return Ok(Picture {
+            width,
+            height,
+            left_offset,
+            top_offset,
+            pixels,
+            posts,
+        });
+    }
+
+    // Makes a picture out of pixels that have already been put together, like a wall texture
+    // Its posts are just the runs of solid pixels in each column
+    pub(crate) fn new(width: usize, height: usize, pixels: Vec<Option<u8>>) -> Picture {
+        let mut posts: Vec<Vec<Post>> = vec![Vec::new(); width];
+        for (x, column) in posts.iter_mut().enumerate() {
+            let mut current: Option<Post> = None;
+            for y in 0..height {
+                match pixels[y * width + x] {
+                    Some(index) => match current.as_mut() {
+                        Some(post) => post.pixels.push(index),
+                        None => {
+                            current = Some(Post {
+                                top: y,
+                                pixels: vec![index],
+                            })
+                        }
+                    },
+                    None => {
+                        if let Some(post) = current.take() {
+                            column.push(post);
+                        }
+                    }
+                }
+            }
+            if let Some(post) = current {
+                column.push(post);
+            }
+        }
+
+        return Picture {
+            width,
+            height,
+            left_offset: 0,
+            top_offset: 0,
+            pixels,
+            posts,
+        };
+    }
+
+    // Palette index at a pixel, None if it's see-through or outside the picture
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        return self.pixels[y * self.width + x];
+    }
+
+    // The posts in a column, empty if it's outside the picture
+    pub fn posts(&self, x: usize) -> &[Post] {
+        match self.posts.get(x) {
+            Some(posts) => posts,
+            None => &[],
+        }
+    }
+
+    // Every pixel going across then down
+    pub fn indexed(&self) -> &[Option<u8>] {
+        return &self.pixels;
+    }
+
+    // Every pixel as red, green, blue and alpha, see-through pixels are all 0
+    pub fn to_rgba(&self, palette: &Palette, palette_num: usize) -> Vec<u8> {
+        let mut rgba: Vec<u8> = Vec::new();
+        for pixel in self.pixels.iter() {
+            match pixel {
+                Some(index) => rgba.extend_from_slice(&palette.color(palette_num, *index)),
+                None => rgba.extend_from_slice(&[0, 0, 0, 0]),
+            }
+        }
+
+        return rgba;
+    }
+
+    // Uploads the picture using the normal palette
+    // Nearest filtering keeps the pixels sharp when they get scaled up
+    pub fn to_texture(&self, palette: &Palette) -> Texture2D {
+        let texture = Texture2D::from_rgba8(
+            self.width as u16,
+            self.height as u16,
+            &self.to_rgba(palette, 0),
+        );
+        texture.set_filter(FilterMode::Nearest);
+
+        return texture;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Builds a picture lump from columns of posts, each post is its top delta and pixels
+    pub(crate) fn build_picture(height: u16, columns: &[Vec<(u8, Vec<u8>)>]) -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0; 8 + columns.len() * 4];
+        <LittleEndian as ByteOrder>::write_u16(&mut data[0..2], columns.len() as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut data[2..4], height);
+
+        for (x, posts) in columns.iter().enumerate() {
+            let column_loc = data.len() as u32;
+            <LittleEndian as ByteOrder>::write_u32(&mut data[8 + x * 4..12 + x * 4], column_loc);
+
+            for (top_delta, pixels) in posts.iter() {
+                data.push(*top_delta);
+                data.push(pixels.len() as u8);
+                data.push(0);
+                data.extend(pixels);
+                data.push(0);
+            }
+            data.push(0xFF);
+        }
+
+        return data;
+    }
+
+    #[test]
+    fn gaps_between_posts_are_see_through() {
+        let data = build_picture(4, &[vec![(0, vec![1]), (2, vec![2, 3])], vec![]]);
+        let picture = Picture::from_bytes("TEST", &data).unwrap();
+
+        assert_eq!((picture.width, picture.height), (2, 4));
+        assert_eq!(picture.pixel(0, 0), Some(1));
+        assert_eq!(picture.pixel(0, 1), None);
+        assert_eq!(picture.pixel(0, 3), Some(3));
+        assert_eq!(picture.pixel(1, 0), None);
+    }
+
+    #[test]
+    fn tall_patch_tops_count_from_the_last_post() {
+        // The third post's top of 10 isn't below 254 so it means 264
+        let data = build_picture(
+            270,
+            &[vec![(0, vec![1; 10]), (254, vec![2]), (10, vec![3, 4])]],
+        );
+        let picture = Picture::from_bytes("TALL", &data).unwrap();
+
+        assert_eq!(picture.pixel(0, 9), Some(1));
+        assert_eq!(picture.pixel(0, 10), None);
+        assert_eq!(picture.pixel(0, 254), Some(2));
+        assert_eq!(picture.pixel(0, 264), Some(3));
+        assert_eq!(picture.pixel(0, 265), Some(4));
+    }
+
+    #[test]
+    fn posts_past_the_bottom_get_cut_off() {
+        let data = build_picture(2, &[vec![(1, vec![5, 6, 7])]]);
+        let picture = Picture::from_bytes("CUT", &data).unwrap();
+
+        assert_eq!(picture.pixel(0, 1), Some(5));
+        assert_eq!(picture.indexed().len(), 2);
+    }
+
+    #[test]
+    fn broken_posts_are_errors() {
+        assert!(Picture::from_bytes("SHORT", &[1, 0, 1, 0]).is_err());
+
+        // Post says it has 3 pixels but the lump ends after 1
+        let mut data = build_picture(4, &[vec![(0, vec![1, 2, 3])]]);
+        data.truncate(data.len() - 4);
+        assert!(matches!(
+            Picture::from_bytes("POST", &data),
+            Err(WadError::MalformedLump { .. })
+        ));
+
+        // Column that never ends
+        let mut data = build_picture(4, &[vec![(0, vec![1])]]);
+        data.pop();
+        assert!(Picture::from_bytes("END", &data).is_err());
+
+        // Column offset past the end of the lump
+        let mut data = build_picture(4, &[vec![]]);
+        <LittleEndian as ByteOrder>::write_u32(&mut data[8..12], 1000);
+        assert!(Picture::from_bytes("OFFSET", &data).is_err());
+    }
+}
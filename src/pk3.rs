@@ -0,0 +1,359 @@
+use crate::wad::{Lump, Namespace, Wad, WadBytes, WadError};
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use flate2::read::DeflateDecoder;
+use std::io::Read;
+use std::sync::Arc;
+
+// PK3 files are ZIP archives where folders take the place of marker lumps
+// Everything gets turned into a list of lumps with the right markers put back in,
+// so the rest of the program can't tell the difference between a PK3 and a WAD
+
+// Folders that become namespaces, everything else in a folder that isn't listed here gets skipped
+const FOLDERS: [(&str, Option<Namespace>); 8] = [
+    ("flats/", Some(Namespace::Flats)),
+    ("sprites/", Some(Namespace::Sprites)),
+    ("patches/", Some(Namespace::Patches)),
+    ("textures/", Some(Namespace::Textures)),
+    ("graphics/", None),
+    ("sounds/", None),
+    ("music/", None),
+    ("colormaps/", None),
+];
+
+// A file in the archive
+struct ZipEntry {
+    path: String,
+    method: u16,
+    compressed_size: usize,
+    size: usize,
+    header_offset: usize,
+}
+
+// Checks if some bytes are the start of a ZIP file, including an empty one
+pub fn is_zip(bytes: &[u8]) -> bool {
+    return bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06");
+}
+
+fn zip_error(name: &str, offset: usize, reason: &str) -> WadError {
+    return WadError::MalformedLump {
+        name: name.to_string(),
+        offset,
+        reason: reason.to_string(),
+    };
+}
+
+// Reads a PK3 into a WAD
+pub fn read_pk3(bytes: Arc<WadBytes>) -> Result<Wad, WadError> {
+    let entries = read_central_directory(&bytes)?;
+
+    let mut global: Vec<Lump> = Vec::new();
+    let mut namespaces: Vec<(Namespace, Vec<Lump>)> = Vec::new();
+    let mut maps: Vec<Lump> = Vec::new();
+
+    for entry in entries.iter() {
+        // Folders have their own entries but there's nothing in them
+        if entry.path.ends_with('/') {
+            continue;
+        }
+
+        let lower_path = entry.path.to_ascii_lowercase();
+        let file_name = entry.path.rsplit('/').next().unwrap_or("");
+
+        // Lump names are the file name without the extension
+        let name = match file_name.split_once('.') {
+            Some((stem, _)) => stem,
+            None => file_name,
+        };
+        if name.is_empty() {
+            continue;
+        }
+
+        // Maps are whole WADs in the maps folder named after the map
+        if lower_path.starts_with("maps/") && lower_path.ends_with(".wad") {
+            let data = read_entry(&bytes, entry)?;
+            let map_wad = Wad::from_bytes(data.data().to_vec())?;
+            if let Some(map_name) = map_wad.map_names().first() {
+                maps.push(Lump::new(name, Vec::new()));
+                for lump in map_wad.map_lumps(map_name.as_str()) {
+                    maps.push(lump.clone());
+                }
+            }
+            continue;
+        }
+
+        // Files in the root are the same as lumps outside of any markers
+        if !entry.path.contains('/') {
+            global.push(read_entry(&bytes, entry)?.renamed(name));
+            continue;
+        }
+
+        let folder = FOLDERS
+            .iter()
+            .find(|(folder, _)| lower_path.starts_with(folder));
+        match folder {
+            Some((_, Some(namespace))) => {
+                let lump = read_entry(&bytes, entry)?.renamed(name);
+                match namespaces.iter_mut().find(|(n, _)| n == namespace) {
+                    Some((_, lumps)) => lumps.push(lump),
+                    None => namespaces.push((*namespace, vec![lump])),
+                }
+            }
+            Some((_, None)) => global.push(read_entry(&bytes, entry)?.renamed(name)),
+            None => continue,
+        }
+    }
+
+    // Global lumps first, then each namespace between its markers, then the maps
+    let mut lumps = global;
+    for (namespace, namespace_lumps) in namespaces {
+        lumps.push(Lump::new(namespace.start_markers()[0], Vec::new()));
+        lumps.extend(namespace_lumps);
+        lumps.push(Lump::new(namespace.end_markers()[0], Vec::new()));
+    }
+    lumps.extend(maps);
+
+    // A PK3 always gets loaded on top of the game like a PWAD
+    return Ok(Wad::from_lumps("PWAD", lumps));
+}
+
+// Finds the list of files at the end of the archive
+fn read_central_directory(file: &[u8]) -> Result<Vec<ZipEntry>, WadError> {
+    // The end record is 22 bytes plus a comment of up to 65535 bytes, so it has to be searched for
+    let search_start = file.len().saturating_sub(22 + 0xFFFF);
+    let mut end_loc: Option<usize> = None;
+    if file.len() >= 22 {
+        for i in (search_start..=file.len() - 22).rev() {
+            if &file[i..i + 4] == b"PK\x05\x06" {
+                end_loc = Some(i);
+                break;
+            }
+        }
+    }
+    let end_loc = match end_loc {
+        Some(end_loc) => end_loc,
+        None => {
+            return Err(zip_error(
+                "PK3",
+                file.len(),
+                "couldn't find the zip directory",
+            ))
+        }
+    };
+
+    let num_of_entries = <LittleEndian as ByteOrder>::read_u16(&file[end_loc + 10..end_loc + 12]);
+    let directory_loc =
+        <LittleEndian as ByteOrder>::read_u32(&file[end_loc + 16..end_loc + 20]) as usize;
+    if num_of_entries == 0xFFFF || directory_loc == 0xFFFFFFFF {
+        return Err(zip_error("PK3", end_loc, "ZIP64 archives aren't supported"));
+    }
+
+    let mut entries: Vec<ZipEntry> = Vec::new();
+    let mut entry_loc = directory_loc;
+    for _ in 0..num_of_entries {
+        if entry_loc + 46 > file.len() || &file[entry_loc..entry_loc + 4] != b"PK\x01\x02" {
+            return Err(zip_error("PK3", entry_loc, "zip directory entry is broken"));
+        }
+
+        let flags = <LittleEndian as ByteOrder>::read_u16(&file[entry_loc + 8..entry_loc + 10]);
+        let method = <LittleEndian as ByteOrder>::read_u16(&file[entry_loc + 10..entry_loc + 12]);
+        let compressed_size =
+            <LittleEndian as ByteOrder>::read_u32(&file[entry_loc + 20..entry_loc + 24]) as usize;
+        let size =
+            <LittleEndian as ByteOrder>::read_u32(&file[entry_loc + 24..entry_loc + 28]) as usize;
+        let name_len =
+            <LittleEndian as ByteOrder>::read_u16(&file[entry_loc + 28..entry_loc + 30]) as usize;
+        let extra_len =
+            <LittleEndian as ByteOrder>::read_u16(&file[entry_loc + 30..entry_loc + 32]) as usize;
+        let comment_len =
+            <LittleEndian as ByteOrder>::read_u16(&file[entry_loc + 32..entry_loc + 34]) as usize;
+        let header_offset =
+            <LittleEndian as ByteOrder>::read_u32(&file[entry_loc + 42..entry_loc + 46]) as usize;
+
+        if entry_loc + 46 + name_len > file.len() {
+            return Err(zip_error("PK3", entry_loc, "file name goes past the end"));
+        }
+
+        // Windows tools sometimes use backslashes
+        let path = String::from_utf8_lossy(&file[entry_loc + 46..entry_loc + 46 + name_len])
+            .replace('\\', "/");
+
+        if flags & 1 != 0 {
+            return Err(zip_error(&path, 0, "encrypted files aren't supported"));
+        }
+
+        entries.push(ZipEntry {
+            path,
+            method,
+            compressed_size,
+            size,
+            header_offset,
+        });
+        entry_loc += 46 + name_len + extra_len + comment_len;
+    }
+
+    return Ok(entries);
+}
+
+// Gets a file out of the archive as a lump named after its path
+// Stored files just point into the archive, compressed ones get decompressed into their own buffer
+fn read_entry(bytes: &Arc<WadBytes>, entry: &ZipEntry) -> Result<Lump, WadError> {
+    let file: &[u8] = bytes;
+
+    // The local header's name and extra field can be different lengths from the ones in the directory
+    let header = entry.header_offset;
+    if header + 30 > file.len() || &file[header..header + 4] != b"PK\x03\x04" {
+        return Err(zip_error(&entry.path, 0, "file header is broken"));
+    }
+    let name_len = <LittleEndian as ByteOrder>::read_u16(&file[header + 26..header + 28]) as usize;
+    let extra_len = <LittleEndian as ByteOrder>::read_u16(&file[header + 28..header + 30]) as usize;
+
+    let start = header + 30 + name_len + extra_len;
+    let end = start + entry.compressed_size;
+    if end > file.len() {
+        return Err(zip_error(&entry.path, 0, "file data goes past the end"));
+    }
+
+    match entry.method {
+        0 => Ok(Lump::from_file(&entry.path, bytes.clone(), start, end)),
+        8 => {
+            let mut data: Vec<u8> = Vec::new();
+            let mut decoder = DeflateDecoder::new(&file[start..end]);
+            if let Err(err) = decoder.read_to_end(&mut data) {
+                return Err(zip_error(
+                    &entry.path,
+                    0,
+                    &format!("couldn't decompress: {}", err),
+                ));
+            }
+            if data.len() != entry.size {
+                return Err(zip_error(
+                    &entry.path,
+                    0,
+                    &format!(
+                        "decompressed to {} bytes instead of {}",
+                        data.len(),
+                        entry.size
+                    ),
+                ));
+            }
+            Ok(Lump::new(&entry.path, data))
+        }
+        method => Err(zip_error(
+            &entry.path,
+            0,
+            &format!("compression method {} isn't supported", method),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::WadWriter;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // Builds a ZIP with each file either stored or deflated, CRCs are left as 0 since they aren't checked
+    fn build_zip(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip: Vec<u8> = Vec::new();
+        let mut directory: Vec<u8> = Vec::new();
+
+        for (path, data, compress) in files.iter() {
+            let stored: Vec<u8> = if *compress {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                data.to_vec()
+            };
+            let method: u16 = if *compress { 8 } else { 0 };
+
+            let mut header = vec![0u8; 30];
+            header[0..4].copy_from_slice(b"PK\x03\x04");
+            <LittleEndian as ByteOrder>::write_u16(&mut header[8..10], method);
+            <LittleEndian as ByteOrder>::write_u32(&mut header[18..22], stored.len() as u32);
+            <LittleEndian as ByteOrder>::write_u32(&mut header[22..26], data.len() as u32);
+            <LittleEndian as ByteOrder>::write_u16(&mut header[26..28], path.len() as u16);
+
+            let mut entry = vec![0u8; 46];
+            entry[0..4].copy_from_slice(b"PK\x01\x02");
+            <LittleEndian as ByteOrder>::write_u16(&mut entry[10..12], method);
+            <LittleEndian as ByteOrder>::write_u32(&mut entry[20..24], stored.len() as u32);
+            <LittleEndian as ByteOrder>::write_u32(&mut entry[24..28], data.len() as u32);
+            <LittleEndian as ByteOrder>::write_u16(&mut entry[28..30], path.len() as u16);
+            <LittleEndian as ByteOrder>::write_u32(&mut entry[42..46], zip.len() as u32);
+            entry.extend(path.as_bytes());
+            directory.extend(entry);
+
+            zip.extend(header);
+            zip.extend(path.as_bytes());
+            zip.extend(stored);
+        }
+
+        let mut end = vec![0u8; 22];
+        end[0..4].copy_from_slice(b"PK\x05\x06");
+        <LittleEndian as ByteOrder>::write_u16(&mut end[10..12], files.len() as u16);
+        <LittleEndian as ByteOrder>::write_u32(&mut end[12..16], directory.len() as u32);
+        <LittleEndian as ByteOrder>::write_u32(&mut end[16..20], zip.len() as u32);
+        zip.extend(directory);
+        zip.extend(end);
+
+        return zip;
+    }
+
+    #[test]
+    fn folders_become_namespaces() {
+        let zip = build_zip(&[
+            ("playpal.lmp", &[1, 2, 3], false),
+            ("flats/", &[], false),
+            ("flats/floor1.lmp", &[7; 4096], true),
+            ("sprites/trooa1.png", &[4, 5], false),
+            ("docs/readme.txt", b"hello", false),
+        ]);
+        let wad = Wad::from_bytes(zip).unwrap();
+
+        assert_eq!(wad.wad_id, "PWAD");
+        assert_eq!(wad.lump_data("PLAYPAL"), Some(&[1u8, 2, 3][..]));
+        assert!(wad.lump("README").is_none());
+
+        let flats = wad.namespace(Namespace::Flats);
+        assert_eq!(flats.len(), 1);
+        assert_eq!(flats[0].name(), "FLOOR1");
+        assert_eq!(flats[0].data(), &[7u8; 4096][..]);
+        assert_eq!(wad.namespace(Namespace::Sprites)[0].name(), "TROOA1");
+    }
+
+    #[test]
+    fn maps_come_from_wads_in_the_maps_folder() {
+        let mut map_wad = WadWriter::new("PWAD");
+        map_wad.add_lump("MAP01", Vec::new());
+        for name in ["THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SECTORS"] {
+            map_wad.add_lump(name, Vec::new());
+        }
+
+        let zip = build_zip(&[("maps/map07.wad", &map_wad.to_bytes(), true)]);
+        let wad = Wad::from_bytes(zip).unwrap();
+
+        // The map is named after the file instead of the marker inside it
+        assert!(wad.has_map("MAP07"));
+        assert!(!wad.has_map("MAP01"));
+    }
+
+    #[test]
+    fn broken_archives_are_errors() {
+        let mut zip = build_zip(&[("playpal.lmp", &[1, 2, 3], false)]);
+
+        // Compression method 14 is LZMA
+        let directory_loc = zip.len() - 22 - 46 - "playpal.lmp".len();
+        zip[directory_loc + 10] = 14;
+        assert!(matches!(
+            Wad::from_bytes(zip.clone()),
+            Err(WadError::MalformedLump { .. })
+        ));
+
+        let cut_off = zip[0..zip.len() - 22].to_vec();
+        assert!(Wad::from_bytes(cut_off).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::wad::{LineDef, SideDef, Vertex};
+
+// How many portal checks a single sector gets before giving up and assuming it can see
+// everything it's connected to, so huge maps can't take forever
+const MAX_CHECKS_PER_SECTOR: usize = 200000;
+
+// The ways a reject table can be made
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectMode {
+    Empty,       // Nothing rejected, which is what most node builders write by default
+    LineOfSight, // Works out which sectors really can't see each other
+}
+
+// A two sided linedef that sight can pass through into another sector
+struct Portal {
+    to_sector: usize,
+
+    // The ends of the line as they look when going through it, left then right
+    left: Vertex,
+    right: Vertex,
+}
+
+// The reject table says which sectors can't possibly see each other
+// Monsters check it before doing a real line of sight check so it has to be conservative,
+// a bit that's set means "definitely can't see", a bit that isn't set means "might be able to"
+pub struct Reject {
+    num_of_sectors: usize,
+
+    // One bit for every pair of sectors, row by row, with the lowest bit of each byte first
+    // PWADs often have a short or empty lump so this can be smaller than the full table
+    bits: Vec<u8>,
+}
+
+impl Reject {
+    // Reads the REJECT lump for a map with a certain number of sectors
+    // Missing bytes just mean every sector can see every other one, which is what the engine does
+    pub fn from_bytes(data: &[u8], num_of_sectors: usize) -> Reject {
+        let size = Reject::expected_size(num_of_sectors);
+        let bits = data[0..data.len().min(size)].to_vec();
+
+        return Reject {
+            num_of_sectors,
+            bits,
+        };
+    }
+
+    // A reject table that doesn't rule anything out
+    pub fn empty(num_of_sectors: usize) -> Reject {
+        return Reject {
+            num_of_sectors,
+            bits: vec![0; Reject::expected_size(num_of_sectors)],
+        };
+    }
+
+    // How many bytes a full table needs
+    pub fn expected_size(num_of_sectors: usize) -> usize {
+        return (num_of_sectors * num_of_sectors).div_ceil(8);
+    }
+
+    pub fn num_of_sectors(&self) -> usize {
+        return self.num_of_sectors;
+    }
+
+    // Checks if a monster in one sector might be able to see into another
+    pub fn can_see(&self, from_sector: usize, to_sector: usize) -> bool {
+        if from_sector >= self.num_of_sectors || to_sector >= self.num_of_sectors {
+            return true;
+        }
+
+        let bit = from_sector * self.num_of_sectors + to_sector;
+        match self.bits.get(bit / 8) {
+            Some(byte) => byte & (1 << (bit % 8)) == 0,
+            None => true,
+        }
+    }
+
+    // Marks whether one sector can see another
+    pub fn set_can_see(&mut self, from_sector: usize, to_sector: usize, can_see: bool) {
+        if from_sector >= self.num_of_sectors || to_sector >= self.num_of_sectors {
+            return;
+        }
+
+        // Fills in the rest of a short table before writing to it
+        self.bits
+            .resize(Reject::expected_size(self.num_of_sectors), 0);
+
+        let bit = from_sector * self.num_of_sectors + to_sector;
+        if can_see {
+            self.bits[bit / 8] &= !(1 << (bit % 8));
+        } else {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    // True if the lump was smaller than it should have been for the number of sectors
+    pub fn is_short(&self) -> bool {
+        return self.bits.len() < Reject::expected_size(self.num_of_sectors);
+    }
+
+    // How many sector pairs the lump actually had bits for
+    pub fn stored_pairs(&self) -> usize {
+        return (self.bits.len() * 8).min(self.num_of_sectors * self.num_of_sectors);
+    }
+
+    // How many sector pairs are marked as not being able to see each other
+    pub fn rejected_pairs(&self) -> usize {
+        let total = self.num_of_sectors * self.num_of_sectors;
+        let mut count = 0;
+        for (i, byte) in self.bits.iter().enumerate() {
+            // The last byte can have padding bits past the end of the table
+            let mut byte = *byte;
+            if (i + 1) * 8 > total {
+                byte &= ((1u16 << (total - i * 8)) - 1) as u8;
+            }
+            count += byte.count_ones() as usize;
+        }
+
+        return count;
+    }
+
+    // Fraction of all the sector pairs that are rejected, 0 for an empty table
+    pub fn fill_ratio(&self) -> f64 {
+        let total = self.num_of_sectors * self.num_of_sectors;
+        if total == 0 {
+            return 0.0;
+        }
+
+        return self.rejected_pairs() as f64 / total as f64;
+    }
+
+    // The table as it would be stored in a REJECT lump, padded out to the full size
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.bits.clone();
+        bytes.resize(Reject::expected_size(self.num_of_sectors), 0);
+
+        return bytes;
+    }
+
+    // Works out the reject table for a map
+    // Doors and lifts can open so every two sided line counts as something you can see through,
+    // the only thing that blocks sight is not being able to draw a straight line through the gaps
+    pub fn build(
+        num_of_sectors: usize,
+        linedefs: &[LineDef],
+        sidedefs: &[SideDef],
+        vertices: &[Vertex],
+        mode: RejectMode,
+    ) -> Reject {
+        let mut reject = Reject::empty(num_of_sectors);
+        if mode == RejectMode::Empty {
+            return reject;
+        }
+
+        // Portals leading out of every sector
+        let mut portals: Vec<Vec<Portal>> = (0..num_of_sectors).map(|_| Vec::new()).collect();
+        let sector_of = |sidedef: Option<u32>| -> Option<usize> {
+            let sector = sidedefs.get(sidedef? as usize)?.facing_sector as usize;
+            if sector < num_of_sectors {
+                return Some(sector);
+            }
+            return None;
+        };
+        for linedef in linedefs.iter() {
+            let front = sector_of(linedef.front_sidedef);
+            let back = sector_of(linedef.back_sidedef);
+            let start = vertices.get(linedef.start as usize);
+            let end = vertices.get(linedef.end as usize);
+
+            if let (Some(front), Some(back), Some(start), Some(end)) = (front, back, start, end) {
+                if front == back {
+                    continue;
+                }
+
+                // The front is on the right of the line so going from front to back the start is on the left
+                portals[front].push(Portal {
+                    to_sector: back,
+                    left: *start,
+                    right: *end,
+                });
+                portals[back].push(Portal {
+                    to_sector: front,
+                    left: *end,
+                    right: *start,
+                });
+            }
+        }
+
+        for from_sector in 0..num_of_sectors {
+            let mut visible = vec![false; num_of_sectors];
+            visible[from_sector] = true;
+
+            let mut on_path = vec![false; num_of_sectors];
+            on_path[from_sector] = true;
+
+            let mut checks = 0;
+            let finished = Reject::flow(
+                from_sector,
+                &portals,
+                &mut Vec::new(),
+                &mut Vec::new(),
+                &mut on_path,
+                &mut visible,
+                &mut checks,
+            );
+
+            // Ran out of time, everything it could possibly reach has to count as visible
+            if !finished {
+                let mut stack = vec![from_sector];
+                while let Some(sector) = stack.pop() {
+                    for portal in portals[sector].iter() {
+                        if !visible[portal.to_sector] {
+                            visible[portal.to_sector] = true;
+                            stack.push(portal.to_sector);
+                        }
+                    }
+                }
+            }
+
+            for (to_sector, can_see) in visible.iter().enumerate() {
+                reject.set_can_see(from_sector, to_sector, *can_see);
+            }
+        }
+
+        return reject;
+    }
+
+    // Follows every chain of portals out of a sector that a straight line could go through
+    // Returns false if it gave up because it took too many checks
+    fn flow(
+        sector: usize,
+        portals: &[Vec<Portal>],
+        lefts: &mut Vec<Vertex>,
+        rights: &mut Vec<Vertex>,
+        on_path: &mut Vec<bool>,
+        visible: &mut Vec<bool>,
+        checks: &mut usize,
+    ) -> bool {
+        for portal in portals[sector].iter() {
+            if on_path[portal.to_sector] {
+                continue;
+            }
+
+            *checks += 1;
+            if *checks > MAX_CHECKS_PER_SECTOR {
+                return false;
+            }
+
+            lefts.push(portal.left);
+            rights.push(portal.right);
+
+            if can_see_through(lefts, rights) {
+                visible[portal.to_sector] = true;
+
+                on_path[portal.to_sector] = true;
+                let finished = Reject::flow(
+                    portal.to_sector,
+                    portals,
+                    lefts,
+                    rights,
+                    on_path,
+                    visible,
+                    checks,
+                );
+                on_path[portal.to_sector] = false;
+
+                if !finished {
+                    return false;
+                }
+            }
+
+            lefts.pop();
+            rights.pop();
+        }
+
+        return true;
+    }
+}
+
+// Which side of the line from a to b a point is on, positive on the left
+fn cross(a: &Vertex, b: &Vertex, point: &Vertex) -> f64 {
+    return (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x);
+}
+
+// Checks if a straight line can go through a chain of portals, which means there's a line with
+// every portal's left end on its left and every right end on its right
+// If there is one then there's also one touching two of the ends, so only those need to be tried
+fn can_see_through(lefts: &[Vertex], rights: &[Vertex]) -> bool {
+    if lefts.len() <= 1 {
+        return true;
+    }
+
+    let points: Vec<&Vertex> = lefts.iter().chain(rights.iter()).collect();
+    for a in points.iter() {
+        for b in points.iter() {
+            if a.x == b.x && a.y == b.y {
+                continue;
+            }
+
+            // Touching counts as seeing through so it never rejects something it shouldn't
+            let separates = lefts.iter().all(|point| cross(a, b, point) >= -0.001)
+                && rights.iter().all(|point| cross(a, b, point) <= 0.001);
+            if separates {
+                return true;
+            }
+        }
+    }
+
+    return false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::LineDefFlags;
+    use crate::wad::LumpName;
+
+    fn sidedef(sector: u32) -> SideDef {
+        return SideDef {
+            x_offset: 0,
+            y_offset: 0,
+            upper_texture: LumpName::new("-"),
+            lower_texture: LumpName::new("-"),
+            middle_texture: LumpName::new("-"),
+            facing_sector: sector,
+        };
+    }
+
+    // A corridor with a room off the bottom left and another off the top left, 0 is the bottom room,
+    // 1 is the corridor and 2 is the top room
+    // The doorways aren't in line or a sight line could slide along the wall through both
+    fn corridor() -> (Vec<LineDef>, Vec<SideDef>, Vec<Vertex>) {
+        let vertices = vec![
+            Vertex { x: 64.0, y: 0.0 },
+            Vertex { x: 64.0, y: 64.0 },
+            Vertex { x: 96.0, y: 128.0 },
+            Vertex { x: 96.0, y: 192.0 },
+        ];
+
+        // Front sidedef is on the right of each line
+        let sidedefs = vec![sidedef(1), sidedef(0), sidedef(2), sidedef(1)];
+        let portal = |start: u32, end: u32, front: u32| LineDef {
+            start,
+            end,
+            special_type: 0,
+            sector_tag: 0,
+            flags: LineDefFlags::empty(),
+            args: [0; 5],
+            front_sidedef: Some(front),
+            back_sidedef: Some(front + 1),
+        };
+        let linedefs = vec![portal(0, 1, 0), portal(3, 2, 2)];
+
+        return (linedefs, sidedefs, vertices);
+    }
+
+    #[test]
+    fn sight_cant_bend_around_corners() {
+        let (linedefs, sidedefs, vertices) = corridor();
+        let reject = Reject::build(3, &linedefs, &sidedefs, &vertices, RejectMode::LineOfSight);
+
+        assert!(reject.can_see(0, 1));
+        assert!(reject.can_see(1, 2));
+        assert!(!reject.can_see(0, 2));
+        assert!(!reject.can_see(2, 0));
+        assert_eq!(reject.rejected_pairs(), 2);
+    }
+
+    #[test]
+    fn empty_tables_reject_nothing() {
+        let (linedefs, sidedefs, vertices) = corridor();
+        let reject = Reject::build(3, &linedefs, &sidedefs, &vertices, RejectMode::Empty);
+
+        assert_eq!(reject.rejected_pairs(), 0);
+        assert_eq!(reject.to_bytes(), vec![0, 0]);
+    }
+
+    #[test]
+    fn tables_come_back_the_same() {
+        let (linedefs, sidedefs, vertices) = corridor();
+        let built = Reject::build(3, &linedefs, &sidedefs, &vertices, RejectMode::LineOfSight);
+        let read = Reject::from_bytes(&built.to_bytes(), 3);
+
+        for from in 0..3 {
+            for to in 0..3 {
+                assert_eq!(read.can_see(from, to), built.can_see(from, to));
+            }
+        }
+    }
+
+    #[test]
+    fn short_tables_can_see_everything_past_the_end() {
+        let reject = Reject::from_bytes(&[0xFF], 4);
+        assert!(reject.is_short());
+        assert!(!reject.can_see(0, 1));
+        assert!(reject.can_see(3, 3));
+    }
+}
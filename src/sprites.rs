@@ -0,0 +1,267 @@
+use crate::picture::Picture;
+use crate::wad::{pos_to_angle, Namespace, Thing, Vertex, WadError};
+use crate::wad_stack::WadStack;
+use std::collections::HashMap;
+
+// Sprite lumps are named like TROOA2A8, a 4 letter prefix, a frame letter and a rotation number,
+// optionally followed by a second frame and rotation that use the same picture flipped
+// Rotation 0 means the frame looks the same from every direction,
+// 1 to 8 go around the thing starting from the front
+
+// The picture to use for one rotation and if it has to be flipped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteView {
+    pub lump: usize, // Index into the sprite lumps
+    pub flip: bool,
+}
+
+// All 8 rotations of a frame, frames with rotation 0 have the same view in all of them
+#[derive(Clone, Debug, Default)]
+pub struct SpriteFrame {
+    pub rotations: [Option<SpriteView>; 8],
+}
+
+pub struct SpriteDef {
+    pub prefix: String,
+
+    // Frames from A to Z, None for letters the sprite doesn't have
+    pub frames: Vec<Option<SpriteFrame>>,
+}
+
+// Every sprite in the loaded files, pictures get decoded the first time they're asked for
+// Like flats, the pictures are found again with the WadStack the sprites were loaded from
+pub struct Sprites {
+    // Which file each sprite lump is in and where it is in that file's directory
+    lumps: Vec<(usize, usize)>,
+    defs: HashMap<String, SpriteDef>,
+    cache: HashMap<usize, Picture>,
+}
+
+// Turns a frame letter and rotation number from a lump name into numbers
+fn parse_frame(frame: u8, rotation: u8) -> Option<(usize, usize)> {
+    if !frame.is_ascii_uppercase() || !(b'0'..=b'8').contains(&rotation) {
+        return None;
+    }
+
+    return Some(((frame - b'A') as usize, (rotation - b'0') as usize));
+}
+
+impl SpriteDef {
+    // Puts a lump into a frame, rotation 0 fills all of them
+    fn install(&mut self, frame: usize, rotation: usize, lump: usize, flip: bool) {
+        if self.frames.len() <= frame {
+            self.frames.resize(frame + 1, None);
+        }
+        let sprite_frame = self.frames[frame].get_or_insert_with(SpriteFrame::default);
+
+        let view = Some(SpriteView { lump, flip });
+        if rotation == 0 {
+            sprite_frame.rotations = [view; 8];
+        } else {
+            sprite_frame.rotations[rotation - 1] = view;
+        }
+    }
+
+    // Which view of a frame to use, rotations are numbered 1 to 8
+    pub fn view(&self, frame: usize, rotation: usize) -> Option<SpriteView> {
+        if !(1..=8).contains(&rotation) {
+            return None;
+        }
+
+        let sprite_frame = self.frames.get(frame)?.as_ref()?;
+        return sprite_frame.rotations[rotation - 1];
+    }
+}
+
+impl Sprites {
+    // Finds the sprites in every file, lumps in PWADs replace the ones with the same name
+    pub fn load(wads: &WadStack) -> Sprites {
+        let lumps = wads.namespace_indexes(Namespace::Sprites);
+
+        let mut defs: HashMap<String, SpriteDef> = HashMap::new();
+        for (i, (wad, lump)) in lumps.iter().enumerate() {
+            let lump = wads.lump_at(*wad, *lump);
+            let name = lump.name().as_bytes();
+            if name.len() != 6 && name.len() != 8 {
+                continue;
+            }
+
+            let prefix = match lump.name().get(..4) {
+                Some(prefix) => prefix.to_string(),
+                None => continue,
+            };
+            let def = defs.entry(prefix.clone()).or_insert(SpriteDef {
+                prefix,
+                frames: Vec::new(),
+            });
+
+            if let Some((frame, rotation)) = parse_frame(name[4], name[5]) {
+                def.install(frame, rotation, i, false);
+            }
+            if name.len() == 8 {
+                if let Some((frame, rotation)) = parse_frame(name[6], name[7]) {
+                    def.install(frame, rotation, i, true);
+                }
+            }
+        }
+
+        return Sprites {
+            lumps,
+            defs,
+            cache: HashMap::new(),
+        };
+    }
+
+    pub fn def(&self, prefix: &str) -> Option<&SpriteDef> {
+        return self.defs.get(&prefix.to_ascii_uppercase());
+    }
+
+    pub fn prefixes(&self) -> Vec<&str> {
+        let mut prefixes: Vec<&str> = self.defs.keys().map(|prefix| prefix.as_str()).collect();
+        prefixes.sort();
+
+        return prefixes;
+    }
+
+    // Decodes one of the sprite lumps
+    pub fn picture(&mut self, wads: &WadStack, lump: usize) -> Result<&Picture, WadError> {
+        if !self.cache.contains_key(&lump) {
+            let (wad_index, lump_index) = self.lumps[lump];
+            let data = wads.lump_at(wad_index, lump_index);
+            let picture = Picture::from_bytes(data.name(), data.data())?;
+            self.cache.insert(lump, picture);
+        }
+
+        return Ok(&self.cache[&lump]);
+    }
+
+    // Picture of a thing seen from somewhere on a frame (0 is A), and if it should be drawn flipped
+    // None if the thing doesn't have a sprite or the sprite doesn't have that frame
+    pub fn thing_sprite(
+        &mut self,
+        wads: &WadStack,
+        thing: &Thing,
+        viewer: &Vertex,
+        frame: usize,
+    ) -> Result<Option<(&Picture, bool)>, WadError> {
+        let prefix = match thing.info() {
+            Some(info) if !info.sprite.is_empty() => info.sprite,
+            _ => return Ok(None),
+        };
+
+        let thing_loc = Vertex {
+            x: thing.x,
+            y: thing.y,
+        };
+        let rotation = view_rotation(viewer, &thing_loc, thing.angle);
+
+        let view = match self.def(prefix).and_then(|def| def.view(frame, rotation)) {
+            Some(view) => view,
+            None => return Ok(None),
+        };
+
+        let picture = self.picture(wads, view.lump)?;
+        return Ok(Some((picture, view.flip)));
+    }
+
+    // Throws away every decoded picture
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+}
+
+// Which rotation from 1 to 8 a thing facing an angle is seen from, 1 is from the front
+// Each rotation covers 45 degrees centered on its direction like in the engine
+pub fn view_rotation(viewer: &Vertex, thing_loc: &Vertex, thing_angle: i16) -> usize {
+    let angle = pos_to_angle(viewer, thing_loc) - thing_angle as f64 + 202.5;
+    let angle = angle.rem_euclid(360.0);
+
+    return (angle / 45.0) as usize % 8 + 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flags::{FlagLayout, ThingFlags};
+    use crate::picture::tests::build_picture;
+    use crate::wad::Wad;
+    use crate::writer::WadWriter;
+
+    fn imp(angle: i16) -> Thing {
+        return Thing {
+            x: 0.0,
+            y: 0.0,
+            angle,
+            thing_type: 3001,
+            flags: ThingFlags::empty(),
+            flag_layout: FlagLayout::Doom,
+            tid: 0,
+            z: 0.0,
+            special: 0,
+            args: [0; 5],
+        };
+    }
+
+    fn sprite_stack() -> WadStack {
+        let mut writer = WadWriter::new("IWAD");
+        writer.add_lump("S_START", Vec::new());
+        writer.add_lump("TROOA1", build_picture(1, &[vec![(0, vec![1])]]));
+        writer.add_lump("TROOA2A8", build_picture(1, &[vec![(0, vec![2])]]));
+        writer.add_lump("TROOA5", build_picture(1, &[vec![(0, vec![5])]]));
+        writer.add_lump("TROOB0", build_picture(1, &[vec![(0, vec![9])]]));
+        writer.add_lump("S_END", Vec::new());
+        let wad = Wad::from_bytes(writer.to_bytes()).unwrap();
+
+        return WadStack::from_iwad("sprites.wad", wad).unwrap();
+    }
+
+    #[test]
+    fn second_frames_in_a_name_are_flipped() {
+        let sprites = Sprites::load(&sprite_stack());
+        let def = sprites.def("troo").unwrap();
+
+        assert!(!def.view(0, 2).unwrap().flip);
+        assert!(def.view(0, 8).unwrap().flip);
+        assert_eq!(def.view(0, 2).unwrap().lump, def.view(0, 8).unwrap().lump);
+        assert_eq!(def.view(0, 3), None);
+
+        // Rotation 0 is the same picture from every side
+        assert_eq!(def.view(1, 1), def.view(1, 6));
+        assert_eq!(def.view(0, 0), None);
+    }
+
+    #[test]
+    fn rotation_1_is_the_front() {
+        let front = Vertex { x: 100.0, y: 0.0 };
+        let back = Vertex { x: -100.0, y: 0.0 };
+        let left = Vertex { x: 0.0, y: 100.0 };
+        let origin = Vertex { x: 0.0, y: 0.0 };
+
+        assert_eq!(view_rotation(&front, &origin, 0), 1);
+        assert_eq!(view_rotation(&back, &origin, 0), 5);
+        assert_eq!(view_rotation(&left, &origin, 0), 3);
+        assert_eq!(view_rotation(&left, &origin, 90), 1);
+    }
+
+    #[test]
+    fn things_pick_the_picture_for_where_they_are_seen_from() {
+        let wads = sprite_stack();
+        let mut sprites = Sprites::load(&wads);
+
+        let viewer = Vertex { x: -100.0, y: 0.0 };
+        let (picture, flip) = sprites
+            .thing_sprite(&wads, &imp(0), &viewer, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(picture.pixel(0, 0), Some(5));
+        assert!(!flip);
+
+        // Teleport destinations don't have a sprite
+        let mut teleport = imp(0);
+        teleport.thing_type = 14;
+        assert!(sprites
+            .thing_sprite(&wads, &teleport, &viewer, 0)
+            .unwrap()
+            .is_none());
+    }
+}
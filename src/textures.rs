@@ -0,0 +1,409 @@
+use crate::picture::Picture;
+use crate::wad::{LumpName, WadError};
+use crate::wad_stack::WadStack;
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use std::collections::HashMap;
+
+// Wall textures aren't stored as pictures, TEXTURE1 and TEXTURE2 say how to build each one
+// out of patches and PNAMES is the list of patch names they point into
+
+// One patch placed on a texture
+pub struct TexturePatch {
+    // Where the patch's top left corner goes on the texture, can be negative
+    pub origin_x: i16,
+    pub origin_y: i16,
+
+    pub patch: LumpName,
+}
+
+pub struct TextureDef {
+    pub name: LumpName,
+    pub width: usize,
+    pub height: usize,
+    pub patches: Vec<TexturePatch>,
+}
+
+// Sidedefs use "-" for a part of the wall that doesn't have a texture
+pub fn is_no_texture(name: &LumpName) -> bool {
+    return name.is_empty() || name == "-";
+}
+
+// Reads the PNAMES lump into a list of patch names
+pub fn read_pnames(data: &[u8]) -> Result<Vec<LumpName>, WadError> {
+    if data.len() < 4 {
+        return Err(WadError::MalformedLump {
+            name: "PNAMES".to_string(),
+            offset: 0,
+            reason: "too small to have a count".to_string(),
+        });
+    }
+
+    let num_of_names = <LittleEndian as ByteOrder>::read_u32(&data[0..4]) as usize;
+    if num_of_names > (data.len() - 4) / 8 {
+        return Err(WadError::MalformedLump {
+            name: "PNAMES".to_string(),
+            offset: 4,
+            reason: format!("{} names don't fit", num_of_names),
+        });
+    }
+
+    let mut names: Vec<LumpName> = Vec::new();
+    for i in 0..num_of_names {
+        let name_loc: usize = 4 + i * 8;
+        names.push(LumpName::from_bytes(&data[name_loc..name_loc + 8]));
+    }
+
+    return Ok(names);
+}
+
+// Reads TEXTURE1 or TEXTURE2, patches are looked up in PNAMES as they're read
+pub fn read_texture_defs(
+    lump_name: &str,
+    data: &[u8],
+    pnames: &[LumpName],
+) -> Result<Vec<TextureDef>, WadError> {
+    let error = |offset: usize, reason: String| WadError::MalformedLump {
+        name: lump_name.to_string(),
+        offset,
+        reason,
+    };
+
+    if data.len() < 4 {
+        return Err(error(0, "too small to have a count".to_string()));
+    }
+
+    let num_of_textures = <LittleEndian as ByteOrder>::read_u32(&data[0..4]) as usize;
+    if num_of_textures > (data.len() - 4) / 4 {
+        return Err(error(
+            4,
+            format!("offsets for {} textures don't fit", num_of_textures),
+        ));
+    }
+
+    let mut defs: Vec<TextureDef> = Vec::new();
+    for i in 0..num_of_textures {
+        let offset_loc: usize = 4 + i * 4;
+        let texture_loc =
+            <LittleEndian as ByteOrder>::read_u32(&data[offset_loc..offset_loc + 4]) as usize;
+
+        // Name, masked flag, width, height, an unused column directory then the patch count
+        if texture_loc + 22 > data.len() {
+            return Err(error(
+                texture_loc,
+                format!("texture {} goes past the end", i),
+            ));
+        }
+        let name = LumpName::from_bytes(&data[texture_loc..texture_loc + 8]);
+        let width =
+            <LittleEndian as ByteOrder>::read_i16(&data[texture_loc + 12..texture_loc + 14]);
+        let height =
+            <LittleEndian as ByteOrder>::read_i16(&data[texture_loc + 14..texture_loc + 16]);
+        let num_of_patches =
+            <LittleEndian as ByteOrder>::read_i16(&data[texture_loc + 20..texture_loc + 22]);
+
+        if width <= 0 || height <= 0 || num_of_patches < 0 {
+            return Err(error(
+                texture_loc,
+                format!("texture {} has a bad size or patch count", name),
+            ));
+        }
+        if texture_loc + 22 + num_of_patches as usize * 10 > data.len() {
+            return Err(error(
+                texture_loc,
+                format!("patches for texture {} go past the end", name),
+            ));
+        }
+
+        let mut patches: Vec<TexturePatch> = Vec::new();
+        for j in 0..(num_of_patches as usize) {
+            // Origin, patch number, then two unused fields
+            let patch_loc: usize = texture_loc + 22 + j * 10;
+            let patch = <LittleEndian as ByteOrder>::read_i16(&data[patch_loc + 4..patch_loc + 6]);
+            if patch < 0 || patch as usize >= pnames.len() {
+                return Err(error(
+                    patch_loc,
+                    format!(
+                        "texture {} uses patch {} but PNAMES only has {}",
+                        name,
+                        patch,
+                        pnames.len()
+                    ),
+                ));
+            }
+
+            patches.push(TexturePatch {
+                origin_x: <LittleEndian as ByteOrder>::read_i16(&data[patch_loc..patch_loc + 2]),
+                origin_y: <LittleEndian as ByteOrder>::read_i16(
+                    &data[patch_loc + 2..patch_loc + 4],
+                ),
+                patch: pnames[patch as usize].clone(),
+            });
+        }
+
+        defs.push(TextureDef {
+            name,
+            width: width as usize,
+            height: height as usize,
+            patches,
+        });
+    }
+
+    return Ok(defs);
+}
+
+// Every texture from TEXTURE1 and TEXTURE2, built into pictures the first time they're asked for
+pub struct Textures {
+    defs: Vec<TextureDef>,
+    by_name: HashMap<LumpName, usize>,
+    cache: HashMap<LumpName, Picture>,
+
+    // Columns covered by more than one patch lose their see-through parts like in vanilla,
+    // source ports keep them so masked midtextures made from several patches still work
+    pub vanilla_columns: bool,
+}
+
+impl Textures {
+    // Reads the texture lumps from the last files that have them, TEXTURE2 is optional
+    pub fn load(wads: &WadStack) -> Result<Textures, WadError> {
+        let pnames = match wads.lump_data("PNAMES") {
+            Some(data) => read_pnames(data)?,
+            None => return Err(WadError::MissingLump("PNAMES".to_string())),
+        };
+
+        let mut defs: Vec<TextureDef> = match wads.lump_data("TEXTURE1") {
+            Some(data) => read_texture_defs("TEXTURE1", data, &pnames)?,
+            None => return Err(WadError::MissingLump("TEXTURE1".to_string())),
+        };
+        if let Some(data) = wads.lump_data("TEXTURE2") {
+            defs.append(&mut read_texture_defs("TEXTURE2", data, &pnames)?);
+        }
+
+        return Ok(Textures::from_defs(defs));
+    }
+
+    pub fn from_defs(defs: Vec<TextureDef>) -> Textures {
+        // The engine searches from the start so the first texture with a name wins
+        let mut by_name: HashMap<LumpName, usize> = HashMap::new();
+        for (i, def) in defs.iter().enumerate() {
+            by_name.entry(def.name.clone()).or_insert(i);
+        }
+
+        return Textures {
+            defs,
+            by_name,
+            cache: HashMap::new(),
+            vanilla_columns: true,
+        };
+    }
+
+    pub fn defs(&self) -> &[TextureDef] {
+        return &self.defs;
+    }
+
+    pub fn def(&self, name: &LumpName) -> Option<&TextureDef> {
+        return self.by_name.get(name).map(|i| &self.defs[*i]);
+    }
+
+    // Gets a texture by name, building it if it hasn't been used yet
+    // None for "-" and for names that aren't defined
+    pub fn get(&mut self, wads: &WadStack, name: &LumpName) -> Result<Option<&Picture>, WadError> {
+        if is_no_texture(name) {
+            return Ok(None);
+        }
+        let index = match self.by_name.get(name) {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+
+        if !self.cache.contains_key(name) {
+            let picture = self.composite(wads, &self.defs[index])?;
+            self.cache.insert(name.clone(), picture);
+        }
+
+        return Ok(self.cache.get(name));
+    }
+
+    // Throws away every built texture, for when the loaded files change
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    // Draws all of a texture's patches on top of each other in order
+    pub fn composite(&self, wads: &WadStack, def: &TextureDef) -> Result<Picture, WadError> {
+        let mut pixels: Vec<Option<u8>> = vec![None; def.width * def.height];
+        let mut patches_in_column: Vec<usize> = vec![0; def.width];
+
+        for texture_patch in def.patches.iter() {
+            // Patches that are missing get skipped instead of stopping the whole texture
+            let data = match wads.lump_data(texture_patch.patch.as_str()) {
+                Some(data) => data,
+                None => continue,
+            };
+            let patch = Picture::from_bytes(texture_patch.patch.as_str(), data)?;
+
+            for patch_x in 0..patch.width {
+                let x = texture_patch.origin_x as i64 + patch_x as i64;
+                if x < 0 || x >= def.width as i64 {
+                    continue;
+                }
+                let x = x as usize;
+                patches_in_column[x] += 1;
+
+                // Posts are drawn whole like R_DrawColumnInCache, even the parts past the bottom
+                // of the patch, as long as they're still on the texture
+                for post in patch.posts(patch_x) {
+                    let mut position = texture_patch.origin_y as i64 + post.top as i64;
+                    let mut count = post.pixels.len() as i64;
+
+                    // Vanilla cuts off the part above the texture without skipping past those pixels,
+                    // so a post that starts above the top gets drawn from its first pixel
+                    if position < 0 {
+                        count += position;
+                        position = 0;
+                    }
+                    if position + count > def.height as i64 {
+                        count = def.height as i64 - position;
+                    }
+
+                    for (i, index) in post.pixels.iter().take(count.max(0) as usize).enumerate() {
+                        pixels[(position as usize + i) * def.width + x] = Some(*index);
+                    }
+                }
+            }
+        }
+
+        // Vanilla builds columns with more than one patch in a buffer that's solid all the way down
+        if self.vanilla_columns {
+            for x in 0..def.width {
+                if patches_in_column[x] < 2 {
+                    continue;
+                }
+                for y in 0..def.height {
+                    let pixel = &mut pixels[y * def.width + x];
+                    if pixel.is_none() {
+                        *pixel = Some(0);
+                    }
+                }
+            }
+        }
+
+        return Ok(Picture::new(def.width, def.height, pixels));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::picture::tests::build_picture;
+    use crate::wad::Wad;
+    use crate::writer::WadWriter;
+
+    fn stack_with_patches(patches: &[(&str, Vec<u8>)]) -> WadStack {
+        let mut writer = WadWriter::new("IWAD");
+        for (name, data) in patches.iter() {
+            writer.add_lump(name, data.clone());
+        }
+        let wad = Wad::from_bytes(writer.to_bytes()).unwrap();
+
+        return WadStack::from_iwad("test.wad", wad).unwrap();
+    }
+
+    fn texture(width: usize, height: usize, patches: &[(&str, i16, i16)]) -> TextureDef {
+        return TextureDef {
+            name: LumpName::new("TEST"),
+            width,
+            height,
+            patches: patches
+                .iter()
+                .map(|(patch, origin_x, origin_y)| TexturePatch {
+                    origin_x: *origin_x,
+                    origin_y: *origin_y,
+                    patch: LumpName::new(patch),
+                })
+                .collect(),
+        };
+    }
+
+    fn column(picture: &Picture, x: usize) -> Vec<Option<u8>> {
+        return (0..picture.height).map(|y| picture.pixel(x, y)).collect();
+    }
+
+    #[test]
+    fn posts_past_the_bottom_of_the_patch_get_drawn() {
+        // The patch is 2 high but its post has 4 pixels
+        let wads =
+            stack_with_patches(&[("LONG", build_picture(2, &[vec![(0, vec![1, 2, 3, 4])]]))]);
+        let textures = Textures::from_defs(Vec::new());
+
+        let built = textures
+            .composite(&wads, &texture(1, 4, &[("LONG", 0, 0)]))
+            .unwrap();
+        assert_eq!(column(&built, 0), vec![Some(1), Some(2), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn posts_above_the_top_start_from_their_first_pixel() {
+        // Two posts right next to each other have to stay separate for this to come out right
+        let wads = stack_with_patches(&[(
+            "PAIR",
+            build_picture(4, &[vec![(0, vec![1, 2]), (2, vec![3, 4])]]),
+        )]);
+        let textures = Textures::from_defs(Vec::new());
+
+        let built = textures
+            .composite(&wads, &texture(1, 3, &[("PAIR", 0, -1)]))
+            .unwrap();
+        assert_eq!(column(&built, 0), vec![Some(1), Some(3), Some(4)]);
+    }
+
+    #[test]
+    fn columns_with_more_than_one_patch_are_solid_in_vanilla() {
+        let wads = stack_with_patches(&[
+            (
+                "TOP",
+                build_picture(1, &[vec![(0, vec![5])], vec![(0, vec![5])]]),
+            ),
+            ("BOTTOM", build_picture(1, &[vec![(0, vec![6])]])),
+        ]);
+        let def = texture(2, 3, &[("TOP", 0, 0), ("BOTTOM", 0, 2), ("MISSING", 0, 0)]);
+
+        let mut textures = Textures::from_defs(Vec::new());
+        let built = textures.composite(&wads, &def).unwrap();
+        assert_eq!(column(&built, 0), vec![Some(5), Some(0), Some(6)]);
+        assert_eq!(column(&built, 1), vec![Some(5), None, None]);
+
+        textures.vanilla_columns = false;
+        let built = textures.composite(&wads, &def).unwrap();
+        assert_eq!(column(&built, 0), vec![Some(5), None, Some(6)]);
+    }
+
+    #[test]
+    fn texture_defs_point_into_pnames() {
+        let mut pnames_data: Vec<u8> = 2u32.to_le_bytes().to_vec();
+        pnames_data.extend(LumpName::new("WALL00_1").to_bytes());
+        pnames_data.extend(LumpName::new("WALL00_2").to_bytes());
+        let pnames = read_pnames(&pnames_data).unwrap();
+
+        // One texture using the second patch
+        let mut data: Vec<u8> = 1u32.to_le_bytes().to_vec();
+        data.extend(8u32.to_le_bytes());
+        data.extend(LumpName::new("BIGDOOR1").to_bytes());
+        for value in [0i16, 0, 128, 96, 0, 0, 1, -8, 4, 1, 1, 0] {
+            data.extend(value.to_le_bytes());
+        }
+
+        let defs = read_texture_defs("TEXTURE1", &data, &pnames).unwrap();
+        assert_eq!(defs[0].name, "BIGDOOR1");
+        assert_eq!((defs[0].width, defs[0].height), (128, 96));
+        assert_eq!(defs[0].patches[0].patch, "WALL00_2");
+        assert_eq!(
+            (defs[0].patches[0].origin_x, defs[0].patches[0].origin_y),
+            (-8, 4)
+        );
+
+        // Patch 2 isn't in PNAMES
+        data[34] = 2;
+        assert!(read_texture_defs("TEXTURE1", &data, &pnames).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+// Everything the game knows about each type of thing in Doom and Doom II
+
+// What sort of thing it is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ThingCategory {
+    PlayerStart,
+    Teleport,
+    Monster,
+    Weapon,
+    Ammo,
+    Health,
+    Armor,
+    Powerup,
+    Key,
+    Obstacle,
+    Light,
+    Decoration,
+    Other,
+}
+
+pub struct ThingInfo {
+    pub thing_type: i16,
+    pub name: &'static str,
+    pub category: ThingCategory,
+
+    // First 4 letters of the sprite names, empty for things that are never drawn
+    pub sprite: &'static str,
+
+    // Size of the thing's collision box, the radius goes out from the middle in both directions
+    pub radius: u16,
+    pub height: u16,
+
+    pub solid: bool,   // Players and monsters can't walk through it
+    pub hanging: bool, // Hangs from the ceiling instead of standing on the floor
+}
+
+// Each row is a type, name, category, sprite, radius and height,
+// then whichever of solid and hanging are true in brackets
+macro_rules! thing_types {
+    // Checks if a flag is in the list
+    (@flag $want:ident) => { false };
+    (@flag solid solid $($rest:ident)*) => { true };
+    (@flag hanging hanging $($rest:ident)*) => { true };
+    (@flag $want:ident $other:ident $($rest:ident)*) => { thing_types!(@flag $want $($rest)*) };
+
+    ($(
+        $thing_type:literal, $name:literal, $category:ident, $sprite:literal,
+        $radius:literal, $height:literal, [$($flag:ident)*];
+    )*) => {
+        static THING_TYPES: &[ThingInfo] = &[$(
+            ThingInfo {
+                thing_type: $thing_type,
+                name: $name,
+                category: $category,
+                sprite: $sprite,
+                radius: $radius,
+                height: $height,
+                solid: thing_types!(@flag solid $($flag)*),
+                hanging: thing_types!(@flag hanging $($flag)*),
+            },
+        )*];
+    };
+}
+
+use ThingCategory::*;
+
+thing_types! {
+    // Starts and teleports
+    1, "Player 1 start", PlayerStart, "PLAY", 16, 56, [];
+    2, "Player 2 start", PlayerStart, "PLAY", 16, 56, [];
+    3, "Player 3 start", PlayerStart, "PLAY", 16, 56, [];
+    4, "Player 4 start", PlayerStart, "PLAY", 16, 56, [];
+    11, "Deathmatch start", PlayerStart, "PLAY", 16, 56, [];
+    14, "Teleport destination", Teleport, "", 20, 16, [];
+
+    // Monsters
+    3004, "Zombieman", Monster, "POSS", 20, 56, [solid];
+    9, "Shotgun guy", Monster, "SPOS", 20, 56, [solid];
+    65, "Heavy weapon dude", Monster, "CPOS", 20, 56, [solid];
+    3001, "Imp", Monster, "TROO", 20, 56, [solid];
+    3002, "Demon", Monster, "SARG", 30, 56, [solid];
+    58, "Spectre", Monster, "SARG", 30, 56, [solid];
+    3006, "Lost soul", Monster, "SKUL", 16, 56, [solid];
+    3005, "Cacodemon", Monster, "HEAD", 31, 56, [solid];
+    69, "Hell knight", Monster, "BOS2", 24, 64, [solid];
+    3003, "Baron of Hell", Monster, "BOSS", 24, 64, [solid];
+    68, "Arachnotron", Monster, "BSPI", 64, 64, [solid];
+    71, "Pain elemental", Monster, "PAIN", 31, 56, [solid];
+    66, "Revenant", Monster, "SKEL", 20, 56, [solid];
+    67, "Mancubus", Monster, "FATT", 48, 64, [solid];
+    64, "Arch-vile", Monster, "VILE", 20, 56, [solid];
+    7, "Spiderdemon", Monster, "SPID", 128, 100, [solid];
+    16, "Cyberdemon", Monster, "CYBR", 40, 110, [solid];
+    84, "Wolfenstein SS", Monster, "SSWV", 20, 56, [solid];
+    72, "Commander Keen", Monster, "KEEN", 16, 72, [solid hanging];
+    88, "Boss brain", Monster, "BBRN", 16, 16, [solid];
+    89, "Monster spawner", Other, "", 20, 32, [];
+    87, "Monster spawn spot", Other, "", 20, 32, [];
+
+    // Weapons
+    2005, "Chainsaw", Weapon, "CSAW", 20, 16, [];
+    2001, "Shotgun", Weapon, "SHOT", 20, 16, [];
+    82, "Super shotgun", Weapon, "SGN2", 20, 16, [];
+    2002, "Chaingun", Weapon, "MGUN", 20, 16, [];
+    2003, "Rocket launcher", Weapon, "LAUN", 20, 16, [];
+    2004, "Plasma gun", Weapon, "PLAS", 20, 16, [];
+    2006, "BFG9000", Weapon, "BFUG", 20, 16, [];
+
+    // Ammo
+    2007, "Clip", Ammo, "CLIP", 20, 16, [];
+    2048, "Box of bullets", Ammo, "AMMO", 20, 16, [];
+    2008, "Shotgun shells", Ammo, "SHEL", 20, 16, [];
+    2049, "Box of shotgun shells", Ammo, "SBOX", 20, 16, [];
+    2010, "Rocket", Ammo, "ROCK", 20, 16, [];
+    2046, "Box of rockets", Ammo, "BROK", 20, 16, [];
+    2047, "Energy cell", Ammo, "CELL", 20, 16, [];
+    17, "Energy cell pack", Ammo, "CELP", 20, 16, [];
+    8, "Backpack", Ammo, "BPAK", 20, 16, [];
+
+    // Health and armor
+    2011, "Stimpack", Health, "STIM", 20, 16, [];
+    2012, "Medikit", Health, "MEDI", 20, 16, [];
+    2014, "Health bonus", Health, "BON1", 20, 16, [];
+    2015, "Armor bonus", Armor, "BON2", 20, 16, [];
+    2018, "Armor", Armor, "ARM1", 20, 16, [];
+    2019, "Megaarmor", Armor, "ARM2", 20, 16, [];
+
+    // Powerups
+    2013, "Soulsphere", Powerup, "SOUL", 20, 16, [];
+    83, "Megasphere", Powerup, "MEGA", 20, 16, [];
+    2022, "Invulnerability", Powerup, "PINV", 20, 16, [];
+    2023, "Berserk", Powerup, "PSTR", 20, 16, [];
+    2024, "Partial invisibility", Powerup, "PINS", 20, 16, [];
+    2025, "Radiation suit", Powerup, "SUIT", 20, 16, [];
+    2026, "Computer area map", Powerup, "PMAP", 20, 16, [];
+    2045, "Light amplification visor", Powerup, "PVIS", 20, 16, [];
+
+    // Keys
+    5, "Blue keycard", Key, "BKEY", 20, 16, [];
+    6, "Yellow keycard", Key, "YKEY", 20, 16, [];
+    13, "Red keycard", Key, "RKEY", 20, 16, [];
+    40, "Blue skull key", Key, "BSKU", 20, 16, [];
+    39, "Yellow skull key", Key, "YSKU", 20, 16, [];
+    38, "Red skull key", Key, "RSKU", 20, 16, [];
+
+    // Lights
+    2028, "Floor lamp", Light, "COLU", 16, 16, [solid];
+    85, "Tall techno floor lamp", Light, "TLMP", 16, 16, [solid];
+    86, "Short techno floor lamp", Light, "TLP2", 16, 16, [solid];
+    34, "Candle", Light, "CAND", 20, 16, [];
+    35, "Candelabra", Light, "CBRA", 16, 16, [solid];
+    44, "Tall blue firestick", Light, "TBLU", 16, 16, [solid];
+    45, "Tall green firestick", Light, "TGRN", 16, 16, [solid];
+    46, "Tall red firestick", Light, "TRED", 16, 16, [solid];
+    55, "Short blue firestick", Light, "SMBT", 16, 16, [solid];
+    56, "Short green firestick", Light, "SMGT", 16, 16, [solid];
+    57, "Short red firestick", Light, "SMRT", 16, 16, [solid];
+    70, "Burning barrel", Light, "FCAN", 10, 16, [solid];
+
+    // Obstacles
+    2035, "Exploding barrel", Obstacle, "BAR1", 10, 42, [solid];
+    48, "Tall techno column", Obstacle, "ELEC", 16, 16, [solid];
+    30, "Tall green pillar", Obstacle, "COL1", 16, 16, [solid];
+    32, "Tall red pillar", Obstacle, "COL3", 16, 16, [solid];
+    31, "Short green pillar", Obstacle, "COL2", 16, 16, [solid];
+    36, "Short green pillar with heart", Obstacle, "COL5", 16, 16, [solid];
+    33, "Short red pillar", Obstacle, "COL4", 16, 16, [solid];
+    37, "Short red pillar with skull", Obstacle, "COL6", 16, 16, [solid];
+    47, "Stalagmite", Obstacle, "SMIT", 16, 16, [solid];
+    43, "Burnt tree", Obstacle, "TRE1", 16, 16, [solid];
+    54, "Large brown tree", Obstacle, "TRE2", 32, 16, [solid];
+    41, "Evil eye", Obstacle, "CEYE", 16, 16, [solid];
+    42, "Floating skull rock", Obstacle, "FSKU", 16, 16, [solid];
+    25, "Impaled human", Obstacle, "POL1", 16, 16, [solid];
+    26, "Twitching impaled human", Obstacle, "POL6", 16, 16, [solid];
+    27, "Skull on a pole", Obstacle, "POL4", 16, 16, [solid];
+    28, "Five skulls shish kebab", Obstacle, "POL2", 16, 16, [solid];
+    29, "Pile of skulls and candles", Obstacle, "POL3", 16, 16, [solid];
+
+    // Hanging from the ceiling, the first ones block and the second set with the same sprites don't
+    49, "Hanging victim, twitching", Obstacle, "GOR1", 16, 68, [solid hanging];
+    50, "Hanging victim, arms out", Obstacle, "GOR2", 16, 84, [solid hanging];
+    51, "Hanging victim, one-legged", Obstacle, "GOR3", 16, 84, [solid hanging];
+    52, "Hanging pair of legs", Obstacle, "GOR4", 16, 68, [solid hanging];
+    53, "Hanging leg", Obstacle, "GOR5", 16, 52, [solid hanging];
+    73, "Hanging victim, guts removed", Obstacle, "HDB1", 16, 88, [solid hanging];
+    74, "Hanging victim, guts and brain removed", Obstacle, "HDB2", 16, 88, [solid hanging];
+    75, "Hanging torso, looking down", Obstacle, "HDB3", 16, 64, [solid hanging];
+    76, "Hanging torso, open skull", Obstacle, "HDB4", 16, 64, [solid hanging];
+    77, "Hanging torso, looking up", Obstacle, "HDB5", 16, 64, [solid hanging];
+    78, "Hanging torso, brain removed", Obstacle, "HDB6", 16, 64, [solid hanging];
+    63, "Hanging victim, twitching", Decoration, "GOR1", 20, 68, [hanging];
+    59, "Hanging victim, arms out", Decoration, "GOR2", 20, 84, [hanging];
+    61, "Hanging victim, one-legged", Decoration, "GOR3", 20, 52, [hanging];
+    60, "Hanging pair of legs", Decoration, "GOR4", 20, 68, [hanging];
+    62, "Hanging leg", Decoration, "GOR5", 20, 52, [hanging];
+
+    // Decorations
+    10, "Bloody mess", Decoration, "PLAY", 20, 16, [];
+    12, "Bloody mess", Decoration, "PLAY", 20, 16, [];
+    15, "Dead player", Decoration, "PLAY", 20, 16, [];
+    18, "Dead zombieman", Decoration, "POSS", 20, 16, [];
+    19, "Dead shotgun guy", Decoration, "SPOS", 20, 16, [];
+    20, "Dead imp", Decoration, "TROO", 20, 16, [];
+    21, "Dead demon", Decoration, "SARG", 20, 16, [];
+    22, "Dead cacodemon", Decoration, "HEAD", 20, 16, [];
+    23, "Dead lost soul", Decoration, "SKUL", 20, 16, [];
+    24, "Pool of blood and flesh", Decoration, "POL5", 20, 16, [];
+    79, "Pool of blood", Decoration, "POB1", 20, 16, [];
+    80, "Pool of blood", Decoration, "POB2", 20, 16, [];
+    81, "Pool of brains", Decoration, "BRS1", 20, 16, [];
+}
+
+// Looks up a thing type, None for types Doom doesn't know about
+pub fn thing_info(thing_type: i16) -> Option<&'static ThingInfo> {
+    return THING_TYPES
+        .iter()
+        .find(|info| info.thing_type == thing_type);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_come_from_the_brackets() {
+        let keen = thing_info(72).unwrap();
+        assert!(keen.solid && keen.hanging);
+
+        let zombieman = thing_info(3004).unwrap();
+        assert!(zombieman.solid && !zombieman.hanging);
+
+        let shotgun = thing_info(2001).unwrap();
+        assert!(!shotgun.solid && !shotgun.hanging);
+    }
+
+    #[test]
+    fn teleport_destinations_are_never_drawn() {
+        assert_eq!(thing_info(14).unwrap().sprite, "");
+    }
+}
@@ -0,0 +1,857 @@
+use crate::flags::{FlagLayout, LineDefFlags, ThingFlags};
+use crate::wad::{LineDef, LumpName, Sector, SideDef, Thing, Vertex, WadError};
+use std::collections::HashMap;
+
+// UDMF maps are stored as text in a TEXTMAP lump instead of as binary records
+// The text is a list of blocks like `vertex { x = 0.0; y = 64.0; }` plus some global settings
+
+// Values that can be assigned to a key
+#[derive(Clone, Debug, PartialEq)]
+pub enum UdmfValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+// Keys from a block which the map structs don't have a place for, with the key names in lower case
+pub type UdmfFields = HashMap<String, UdmfValue>;
+
+// Everything in a TEXTMAP that didn't fit into the regular map structs
+// Each vector has one entry per object in the same order as the map's vectors
+#[derive(Default)]
+pub struct UdmfExtras {
+    pub namespace: String,
+    pub globals: UdmfFields, // Settings outside of any block besides the namespace
+    pub things: Vec<UdmfFields>,
+    pub linedefs: Vec<UdmfFields>,
+    pub sidedefs: Vec<UdmfFields>,
+    pub vertices: Vec<UdmfFields>,
+    pub sectors: Vec<UdmfFields>,
+
+    // Blocks of a type the parser doesn't know about, kept with their type name
+    pub other_blocks: Vec<(String, UdmfFields)>,
+}
+
+// The objects read out of a TEXTMAP
+pub struct UdmfMap {
+    pub things: Vec<Thing>,
+    pub linedefs: Vec<LineDef>,
+    pub sidedefs: Vec<SideDef>,
+    pub vertices: Vec<Vertex>,
+    pub sectors: Vec<Sector>,
+    pub extras: UdmfExtras,
+}
+
+// The pieces the text gets split up into
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    OpenBrace,
+    CloseBrace,
+    Equals,
+    Semicolon,
+}
+
+// Makes an error pointing at a byte in the TEXTMAP
+fn textmap_error(offset: usize, reason: String) -> WadError {
+    return WadError::MalformedLump {
+        name: "TEXTMAP".to_string(),
+        offset,
+        reason,
+    };
+}
+
+// Splits the text into tokens along with the byte offset each one started at
+fn tokenize(text: &[u8]) -> Result<Vec<(usize, Token)>, WadError> {
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    let mut i = 0;
+
+    while i < text.len() {
+        let c = text[i];
+
+        // Whitespace and comments
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if text[i..].starts_with(b"//") {
+            while i < text.len() && text[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if text[i..].starts_with(b"/*") {
+            match text[i + 2..].windows(2).position(|pair| pair == b"*/") {
+                Some(end) => i += end + 4,
+                None => return Err(textmap_error(i, "comment is never closed".to_string())),
+            }
+            continue;
+        }
+
+        let start = i;
+        match c {
+            b'{' => {
+                tokens.push((start, Token::OpenBrace));
+                i += 1;
+            }
+            b'}' => {
+                tokens.push((start, Token::CloseBrace));
+                i += 1;
+            }
+            b'=' => {
+                tokens.push((start, Token::Equals));
+                i += 1;
+            }
+            b';' => {
+                tokens.push((start, Token::Semicolon));
+                i += 1;
+            }
+            b'"' => {
+                // Strings can have backslash escapes in them
+                let mut string: Vec<u8> = Vec::new();
+                i += 1;
+                loop {
+                    if i >= text.len() {
+                        return Err(textmap_error(start, "string is never closed".to_string()));
+                    }
+                    match text[i] {
+                        b'"' => break,
+                        b'\\' if i + 1 < text.len() => {
+                            string.push(text[i + 1]);
+                            i += 2;
+                        }
+                        byte => {
+                            string.push(byte);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                tokens.push((
+                    start,
+                    Token::Str(String::from_utf8_lossy(&string).to_string()),
+                ));
+            }
+            b'0'..=b'9' | b'-' | b'+' | b'.' => {
+                while i < text.len()
+                    && (text[i].is_ascii_alphanumeric() || b"+-.".contains(&text[i]))
+                {
+                    // A sign is only part of the number at the start or after an exponent
+                    if (text[i] == b'-' || text[i] == b'+')
+                        && i != start
+                        && !(text[i - 1] == b'e' || text[i - 1] == b'E')
+                    {
+                        break;
+                    }
+                    i += 1;
+                }
+                let number = String::from_utf8_lossy(&text[start..i]).to_string();
+                tokens.push((start, parse_number(&number, start)?));
+            }
+            _ if c.is_ascii_alphabetic() || c == b'_' => {
+                while i < text.len()
+                    && (text[i].is_ascii_alphanumeric() || text[i] == b'_' || text[i] == b':')
+                {
+                    i += 1;
+                }
+                let ident = String::from_utf8_lossy(&text[start..i]).to_string();
+                tokens.push((start, Token::Ident(ident)));
+            }
+            _ => {
+                return Err(textmap_error(
+                    start,
+                    format!("unexpected character {:?}", c as char),
+                ))
+            }
+        }
+    }
+
+    return Ok(tokens);
+}
+
+// Numbers can be decimal, hex with 0x, octal with a leading 0, or floats
+fn parse_number(number: &str, offset: usize) -> Result<Token, WadError> {
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number.strip_prefix('+').unwrap_or(number)),
+    };
+
+    let int = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1
+        && digits.starts_with('0')
+        && digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        i64::from_str_radix(&digits[1..], 8).ok()
+    } else if digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse::<i64>().ok()
+    } else {
+        None
+    };
+
+    if let Some(int) = int {
+        return Ok(Token::Int(if negative { -int } else { int }));
+    }
+
+    match number.parse::<f64>() {
+        Ok(float) => Ok(Token::Float(float)),
+        Err(_) => Err(textmap_error(
+            offset,
+            format!("{:?} isn't a number", number),
+        )),
+    }
+}
+
+// A block's type, where it starts in the text and its fields
+type ParsedBlock = (String, usize, UdmfFields);
+
+// Reads the blocks and global assignments out of the tokens
+fn parse_blocks(
+    tokens: &[(usize, Token)],
+    text_len: usize,
+) -> Result<(UdmfFields, Vec<ParsedBlock>), WadError> {
+    let mut globals: UdmfFields = HashMap::new();
+    let mut blocks: Vec<ParsedBlock> = Vec::new();
+
+    // Gets the token at a position or complains that the text ended early
+    let token_at = |i: usize| -> Result<&(usize, Token), WadError> {
+        return tokens.get(i).ok_or_else(|| {
+            textmap_error(text_len, "text ends in the middle of something".to_string())
+        });
+    };
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let (offset, token) = token_at(i)?;
+        let name_offset = *offset;
+        let name = match token {
+            Token::Ident(name) => name.to_lowercase(),
+            other => {
+                return Err(textmap_error(
+                    *offset,
+                    format!("expected a name, found {:?}", other),
+                ))
+            }
+        };
+
+        let (offset, token) = token_at(i + 1)?;
+        match token {
+            Token::Equals => {
+                let (value, next) = parse_assignment(tokens, i + 2, text_len)?;
+                globals.insert(name, value);
+                i = next;
+            }
+            Token::OpenBrace => {
+                let mut fields: UdmfFields = HashMap::new();
+                i += 2;
+                loop {
+                    let (offset, token) = token_at(i)?;
+                    match token {
+                        Token::CloseBrace => {
+                            i += 1;
+                            break;
+                        }
+                        Token::Ident(key) => {
+                            let (offset, token) = token_at(i + 1)?;
+                            if *token != Token::Equals {
+                                return Err(textmap_error(
+                                    *offset,
+                                    format!("expected = after {}", key),
+                                ));
+                            }
+                            let (value, next) = parse_assignment(tokens, i + 2, text_len)?;
+                            fields.insert(key.to_lowercase(), value);
+                            i = next;
+                        }
+                        other => {
+                            return Err(textmap_error(
+                                *offset,
+                                format!("expected a key or }}, found {:?}", other),
+                            ))
+                        }
+                    }
+                }
+                blocks.push((name, name_offset, fields));
+            }
+            other => {
+                return Err(textmap_error(
+                    *offset,
+                    format!("expected = or {{ after {}, found {:?}", name, other),
+                ))
+            }
+        }
+    }
+
+    return Ok((globals, blocks));
+}
+
+// Reads `value ;` starting at a token and returns the value and where the next token is
+fn parse_assignment(
+    tokens: &[(usize, Token)],
+    i: usize,
+    text_len: usize,
+) -> Result<(UdmfValue, usize), WadError> {
+    let ended_early = || {
+        textmap_error(
+            text_len,
+            "text ends in the middle of an assignment".to_string(),
+        )
+    };
+
+    let (offset, token) = tokens.get(i).ok_or_else(ended_early)?;
+    let value = match token {
+        Token::Int(int) => UdmfValue::Int(*int),
+        Token::Float(float) => UdmfValue::Float(*float),
+        Token::Str(string) => UdmfValue::Str(string.clone()),
+        Token::Ident(ident) if ident.eq_ignore_ascii_case("true") => UdmfValue::Bool(true),
+        Token::Ident(ident) if ident.eq_ignore_ascii_case("false") => UdmfValue::Bool(false),
+        Token::Ident(ident) => UdmfValue::Str(ident.clone()),
+        other => {
+            return Err(textmap_error(
+                *offset,
+                format!("expected a value, found {:?}", other),
+            ))
+        }
+    };
+
+    let (offset, token) = tokens.get(i + 1).ok_or_else(ended_early)?;
+    if *token != Token::Semicolon {
+        return Err(textmap_error(
+            *offset,
+            "expected ; after a value".to_string(),
+        ));
+    }
+
+    return Ok((value, i + 2));
+}
+
+// Takes recognised keys out of a block, whatever is left over ends up in the extras
+struct Block {
+    offset: usize, // Where the block starts in the text, for errors
+    fields: UdmfFields,
+}
+
+impl Block {
+    fn int(&mut self, key: &str, default: i64) -> i64 {
+        match self.fields.remove(key) {
+            Some(UdmfValue::Int(int)) => int,
+            Some(UdmfValue::Float(float)) => float as i64,
+            Some(UdmfValue::Bool(b)) => b as i64,
+            Some(other) => {
+                // Puts back things that are the wrong type so they aren't lost
+                self.fields.insert(key.to_string(), other);
+                default
+            }
+            None => default,
+        }
+    }
+
+    // Same as int but has to fit between min and max, what says which block it is for errors
+    fn ranged(
+        &mut self,
+        key: &str,
+        default: i64,
+        min: i64,
+        max: i64,
+        what: &str,
+    ) -> Result<i64, WadError> {
+        let value = self.int(key, default);
+        if value < min || value > max {
+            return Err(textmap_error(
+                self.offset,
+                format!(
+                    "{} has {} = {}, which has to be from {} to {}",
+                    what, key, value, min, max
+                ),
+            ));
+        }
+
+        return Ok(value);
+    }
+
+    // An int that has to fit in an i16 like the binary formats use
+    fn short(&mut self, key: &str, default: i64, what: &str) -> Result<i16, WadError> {
+        return Ok(self.ranged(key, default, i16::MIN as i64, i16::MAX as i64, what)? as i16);
+    }
+
+    // Reads an index into one of the map's lists, a missing key or -1 means there isn't one
+    // Anything else that doesn't fit in 32 bits is an error instead of pointing at the wrong object
+    fn index(&mut self, key: &str, what: &str) -> Result<Option<u32>, WadError> {
+        let value = self.int(key, -1);
+        if value == -1 {
+            return Ok(None);
+        }
+        if value < 0 || value > u32::MAX as i64 {
+            return Err(textmap_error(
+                self.offset,
+                format!(
+                    "{} has {} = {}, which isn't a valid index",
+                    what, key, value
+                ),
+            ));
+        }
+
+        return Ok(Some(value as u32));
+    }
+
+    // Same as index but for keys the spec says every block has to have
+    fn required_index(&mut self, key: &str, what: &str) -> Result<u32, WadError> {
+        match self.index(key, what)? {
+            Some(index) => Ok(index),
+            None => Err(textmap_error(
+                self.offset,
+                format!("{} doesn't have a {}", what, key),
+            )),
+        }
+    }
+
+    fn float(&mut self, key: &str, default: f64) -> f64 {
+        match self.fields.remove(key) {
+            Some(UdmfValue::Float(float)) => float,
+            Some(UdmfValue::Int(int)) => int as f64,
+            Some(other) => {
+                self.fields.insert(key.to_string(), other);
+                default
+            }
+            None => default,
+        }
+    }
+
+    fn bool(&mut self, key: &str) -> bool {
+        match self.fields.remove(key) {
+            Some(UdmfValue::Bool(b)) => b,
+            Some(other) => {
+                self.fields.insert(key.to_string(), other);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn name(&mut self, key: &str, default: &str) -> LumpName {
+        match self.fields.remove(key) {
+            Some(UdmfValue::Str(string)) => LumpName::new(&string),
+            Some(other) => {
+                self.fields.insert(key.to_string(), other);
+                LumpName::new(default)
+            }
+            None => LumpName::new(default),
+        }
+    }
+
+    // A value that's a byte in Hexen format, anything bigger is left in the extras and read as 0
+    fn byte(&mut self, key: &str) -> u8 {
+        if let Some(UdmfValue::Int(int)) = self.fields.get(key) {
+            if (0..=255).contains(int) {
+                let byte = *int as u8;
+                self.fields.remove(key);
+                return byte;
+            }
+        }
+
+        return 0;
+    }
+
+    // Special arguments are bytes in Hexen format so anything bigger is left in the extras
+    fn args(&mut self) -> [u8; 5] {
+        let mut args = [0u8; 5];
+        for (i, arg) in args.iter_mut().enumerate() {
+            *arg = self.byte(&format!("arg{}", i));
+        }
+
+        return args;
+    }
+
+    // Sets a bit in a flag word if a key is true
+    fn flag(&mut self, key: &str, bit: u16, flags: &mut u16) {
+        if self.bool(key) {
+            *flags |= bit;
+        }
+    }
+}
+
+// Namespaces that store things and linedefs like Hexen instead of like Doom
+fn uses_hexen_layout(namespace: &str) -> bool {
+    return matches!(namespace, "hexen" | "zdoom" | "zdoomtranslated" | "vavoom");
+}
+
+// Reads a whole TEXTMAP lump
+pub fn parse_textmap(data: &[u8]) -> Result<UdmfMap, WadError> {
+    let tokens = tokenize(data)?;
+    let (mut globals, blocks) = parse_blocks(&tokens, data.len())?;
+
+    let namespace = match globals.remove("namespace") {
+        Some(UdmfValue::Str(namespace)) => namespace.to_lowercase(),
+        _ => return Err(textmap_error(0, "no namespace given".to_string())),
+    };
+    let hexen_layout = uses_hexen_layout(&namespace);
+
+    let mut map = UdmfMap {
+        things: Vec::new(),
+        linedefs: Vec::new(),
+        sidedefs: Vec::new(),
+        vertices: Vec::new(),
+        sectors: Vec::new(),
+        extras: UdmfExtras {
+            namespace: namespace.clone(),
+            globals,
+            ..Default::default()
+        },
+    };
+
+    for (kind, offset, fields) in blocks {
+        let mut block = Block { offset, fields };
+        match kind.as_str() {
+            "vertex" => {
+                map.vertices.push(Vertex {
+                    x: block.float("x", 0.0),
+                    y: block.float("y", 0.0),
+                });
+                map.extras.vertices.push(block.fields);
+            }
+            "linedef" => {
+                let what = format!("linedef {}", map.linedefs.len());
+                map.linedefs.push(linedef_from_block(
+                    &mut block,
+                    &what,
+                    &namespace,
+                    hexen_layout,
+                )?);
+                map.extras.linedefs.push(block.fields);
+            }
+            "sidedef" => {
+                let what = format!("sidedef {}", map.sidedefs.len());
+                map.sidedefs.push(SideDef {
+                    x_offset: block.short("offsetx", 0, &what)?,
+                    y_offset: block.short("offsety", 0, &what)?,
+                    upper_texture: block.name("texturetop", "-"),
+                    lower_texture: block.name("texturebottom", "-"),
+                    middle_texture: block.name("texturemiddle", "-"),
+                    facing_sector: block.required_index("sector", &what)?,
+                });
+                map.extras.sidedefs.push(block.fields);
+            }
+            "sector" => {
+                let what = format!("sector {}", map.sectors.len());
+                map.sectors.push(Sector {
+                    floor_height: block.short("heightfloor", 0, &what)?,
+                    ceiling_height: block.short("heightceiling", 0, &what)?,
+                    floor_texture: block.name("texturefloor", "-"),
+                    ceiling_texture: block.name("textureceiling", "-"),
+                    light_level: block.short("lightlevel", 160, &what)?,
+                    special_type: block.short("special", 0, &what)?,
+                    tag_number: block.short("id", 0, &what)?,
+                });
+                map.extras.sectors.push(block.fields);
+            }
+            "thing" => {
+                let what = format!("thing {}", map.things.len());
+                map.things.push(thing_from_block(
+                    &mut block,
+                    &what,
+                    &namespace,
+                    hexen_layout,
+                )?);
+                map.extras.things.push(block.fields);
+            }
+            _ => map.extras.other_blocks.push((kind, block.fields)),
+        }
+    }
+
+    return Ok(map);
+}
+
+fn linedef_from_block(
+    block: &mut Block,
+    what: &str,
+    namespace: &str,
+    hexen_layout: bool,
+) -> Result<LineDef, WadError> {
+    // Builds the flags the same way they'd be stored in a binary map
+    let mut flags: u16 = 0;
+    block.flag("blocking", 0x1, &mut flags);
+    block.flag("blockmonsters", 0x2, &mut flags);
+    block.flag("twosided", 0x4, &mut flags);
+    block.flag("dontpegtop", 0x8, &mut flags);
+    block.flag("dontpegbottom", 0x10, &mut flags);
+    block.flag("secret", 0x20, &mut flags);
+    block.flag("blocksound", 0x40, &mut flags);
+    block.flag("dontdraw", 0x80, &mut flags);
+    block.flag("mapped", 0x100, &mut flags);
+
+    if hexen_layout {
+        block.flag("repeatspecial", 0x200, &mut flags);
+
+        // Hexen can only store one way of activating a special, the first one found wins
+        // and any others stay in the extras
+        let activations = [
+            ("playercross", 0),
+            ("playeruse", 1),
+            ("monstercross", 2),
+            ("impact", 3),
+            ("playerpush", 4),
+            ("missilecross", 5),
+        ];
+        for (key, activation) in activations {
+            if block.fields.get(key) == Some(&UdmfValue::Bool(true)) {
+                block.fields.remove(key);
+                flags |= activation << 10;
+                break;
+            }
+        }
+    } else {
+        // Boom's pass through flag is only in the Doom style namespaces
+        block.flag("passuse", 0x200, &mut flags);
+        if namespace == "strife" {
+            block.flag("jumpover", 0x200, &mut flags);
+            block.flag("blockfloaters", 0x400, &mut flags);
+            block.flag("translucent", 0x800, &mut flags);
+        }
+    }
+
+    let id = block.short("id", -1, what)?;
+
+    return Ok(LineDef {
+        start: block.required_index("v1", what)?,
+        end: block.required_index("v2", what)?,
+        special_type: block.short("special", 0, what)?,
+        // A line ID of -1 means it doesn't have one, which is 0 in the binary formats
+        sector_tag: id.max(0),
+        flags: LineDefFlags::from_bits_retain(flags),
+        args: block.args(),
+        front_sidedef: Some(block.required_index("sidefront", what)?),
+        back_sidedef: block.index("sideback", what)?,
+    });
+}
+
+fn thing_from_block(
+    block: &mut Block,
+    what: &str,
+    namespace: &str,
+    hexen_layout: bool,
+) -> Result<Thing, WadError> {
+    // Doom only has 3 skill bits so the 2 easiest and 2 hardest skills share one each
+    let skill1 = block.bool("skill1");
+    let skill2 = block.bool("skill2");
+    let skill3 = block.bool("skill3");
+    let skill4 = block.bool("skill4");
+    let skill5 = block.bool("skill5");
+    let easy = skill1 || skill2;
+    let medium = skill3;
+    let hard = skill4 || skill5;
+
+    let mut flags: u16 = 0;
+    if easy {
+        flags |= 0x1;
+    }
+    if medium {
+        flags |= 0x2;
+    }
+    if hard {
+        flags |= 0x4;
+    }
+
+    let ambush;
+    let single;
+    let flag_layout;
+    if hexen_layout {
+        flag_layout = FlagLayout::Hexen;
+        ambush = block.bool("ambush");
+        single = block.bool("single");
+        if ambush {
+            flags |= 0x8;
+        }
+        block.flag("dormant", 0x10, &mut flags);
+        block.flag("class1", 0x20, &mut flags);
+        block.flag("class2", 0x40, &mut flags);
+        block.flag("class3", 0x80, &mut flags);
+        if single {
+            flags |= 0x100;
+        }
+        block.flag("coop", 0x200, &mut flags);
+        block.flag("dm", 0x400, &mut flags);
+    } else if namespace == "strife" {
+        flag_layout = FlagLayout::Strife;
+        block.flag("standing", 0x8, &mut flags);
+        single = block.bool("single");
+        if !single {
+            flags |= 0x10;
+        }
+        ambush = block.bool("ambush");
+        if ambush {
+            flags |= 0x20;
+        }
+        block.flag("strifeally", 0x40, &mut flags);
+        block.flag("translucent", 0x100, &mut flags);
+        block.flag("invisible", 0x200, &mut flags);
+    } else {
+        flag_layout = FlagLayout::Doom;
+        ambush = block.bool("ambush");
+        single = block.bool("single");
+        if ambush {
+            flags |= 0x8;
+        }
+        if !single {
+            flags |= 0x10;
+        }
+        // Boom and MBF flags, stored as "not in" instead of "in"
+        if !block.bool("dm") {
+            flags |= 0x20;
+        }
+        if !block.bool("coop") {
+            flags |= 0x40;
+        }
+        block.flag("friend", 0x80, &mut flags);
+    }
+
+    return Ok(Thing {
+        x: block.float("x", 0.0),
+        y: block.float("y", 0.0),
+        angle: block.short("angle", 0, what)?,
+        thing_type: block.short("type", 0, what)?,
+        flags: ThingFlags::from_bits_retain(flags),
+        flag_layout,
+        tid: block.short("id", 0, what)?,
+        z: block.float("height", 0.0),
+        special: block.byte("special"),
+        args: block.args(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thing_positions_keep_fractions() {
+        let map = parse_textmap(
+            b"namespace = \"zdoom\"; thing { x = 40000.5; y = -12.25; height = 8.5; type = 1; }",
+        )
+        .unwrap();
+        assert_eq!(map.things[0].x, 40000.5);
+        assert_eq!(map.things[0].y, -12.25);
+        assert_eq!(map.things[0].z, 8.5);
+    }
+
+    #[test]
+    fn big_indexes_are_kept() {
+        let map = parse_textmap(
+            b"namespace = \"doom\"; linedef { v1 = 70000; v2 = 1; sidefront = 40000; }",
+        )
+        .unwrap();
+        assert_eq!(map.linedefs[0].start, 70000);
+        assert_eq!(map.linedefs[0].front_sidedef, Some(40000));
+        assert_eq!(map.linedefs[0].back_sidedef, None);
+    }
+
+    #[test]
+    fn values_too_big_for_16_bits_are_errors() {
+        let result = parse_textmap(b"namespace = \"doom\"; sector { heightfloor = 70000; }");
+        assert!(matches!(result, Err(WadError::MalformedLump { .. })));
+
+        let result =
+            parse_textmap(b"namespace = \"doom\"; linedef { v1 = -5; v2 = 1; sidefront = 0; }");
+        assert!(matches!(result, Err(WadError::MalformedLump { .. })));
+    }
+
+    #[test]
+    fn bad_values_say_which_block_they_are_in() {
+        let text =
+            b"namespace = \"doom\";\nvertex { x = 0; y = 0; }\nsector { heightfloor = 70000; }";
+        match parse_textmap(text) {
+            Err(WadError::MalformedLump { offset, .. }) => assert_eq!(offset, 45),
+            other => panic!("expected an error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn big_thing_specials_are_kept_in_the_extras() {
+        let map =
+            parse_textmap(b"namespace = \"zdoom\"; thing { type = 1; special = 300; arg0 = 7; }")
+                .unwrap();
+        assert_eq!(map.things[0].special, 0);
+        assert_eq!(map.things[0].args[0], 7);
+        assert_eq!(
+            map.extras.things[0].get("special"),
+            Some(&UdmfValue::Int(300))
+        );
+    }
+
+    fn token_list(text: &str) -> Vec<Token> {
+        return tokenize(text.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(|(_, token)| token)
+            .collect();
+    }
+
+    #[test]
+    fn tokenizer_skips_comments_and_reads_escapes() {
+        let tokens = token_list("// line\nx /* block */ = \"a \\\"b\\\"\";");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("x".to_string()),
+                Token::Equals,
+                Token::Str("a \"b\"".to_string()),
+                Token::Semicolon,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizer_reads_every_kind_of_number() {
+        let tokens = token_list("0x1F 010 -12 +3 1.5e2 -.25 1e-3");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Int(31),
+                Token::Int(8),
+                Token::Int(-12),
+                Token::Int(3),
+                Token::Float(150.0),
+                Token::Float(-0.25),
+                Token::Float(0.001),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizer_errors_on_unclosed_text() {
+        assert!(tokenize(b"x = \"never closed;").is_err());
+        assert!(tokenize(b"/* never closed").is_err());
+        assert!(tokenize(b"x = 1 # 2;").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_and_blocks_are_kept() {
+        let map = parse_textmap(
+            b"namespace = \"zdoom\"; gravity = 400.0;
+            vertex { x = 1.5; y = 2; }
+            sector { texturefloor = \"FLOOR0_1\"; lightcolor = 0xFF0000; }
+            mystery { a = true; }",
+        )
+        .unwrap();
+
+        assert_eq!(map.vertices, vec![Vertex { x: 1.5, y: 2.0 }]);
+        assert_eq!(map.sectors[0].floor_texture, "FLOOR0_1");
+        assert_eq!(map.sectors[0].light_level, 160);
+        assert_eq!(map.extras.namespace, "zdoom");
+        assert_eq!(
+            map.extras.globals.get("gravity"),
+            Some(&UdmfValue::Float(400.0))
+        );
+        assert_eq!(
+            map.extras.sectors[0].get("lightcolor"),
+            Some(&UdmfValue::Int(0xFF0000))
+        );
+        assert_eq!(map.extras.other_blocks[0].0, "mystery");
+    }
+}
@@ -1,20 +1,33 @@
+use crate::blockmap::Blockmap;
+use crate::flags::{FlagLayout, GameMode, LineDefFlags, Skill, ThingFlags};
+use crate::flats;
+use crate::nodebuilder;
+use crate::nodebuilder::BuildOptions;
+use crate::nodes;
+use crate::nodes::NodeFormat;
+use crate::pk3;
+use crate::reject::{Reject, RejectMode};
+use crate::things;
+use crate::things::ThingInfo;
+use crate::udmf;
+use crate::udmf::UdmfExtras;
 use byteorder::ByteOrder;
 use byteorder::LittleEndian;
+use memmap2::Mmap;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::ops::Deref;
 use std::str;
-
-// Reads the bit at the index and returns it as a bool
-fn bool_from_i16(int: i16, index: u32) -> bool {
-    return int % i16::pow(2, index + 1) != 0;
-}
+use std::sync::{Arc, OnceLock};
 
 // Checks if the vertex is in a bounding box
 fn check_box(loc: &Vertex, bounding_box: &Vec<i16>) -> bool {
-    return loc.y < bounding_box[0]
-        && loc.y > bounding_box[1]
-        && loc.x > bounding_box[2]
-        && loc.x < bounding_box[3];
+    return loc.y < bounding_box[0] as f64
+        && loc.y > bounding_box[1] as f64
+        && loc.x > bounding_box[2] as f64
+        && loc.x < bounding_box[3] as f64;
 }
 
 // Returns false if to the left, true to the right
@@ -32,14 +45,14 @@ fn check_line(start: &Vec<i16>, change: &Vec<i16>, loc: &Vertex) -> bool {
     let invert = change[1] < 0;
 
     if is_vertical {
-        return (loc.x > start[0]) != invert;
+        return (loc.x > start[0] as f64) != invert;
     } else if slope == 0.0 {
-        return (loc.y < start[1]) != (change[0] < 0);
+        return (loc.y < start[1] as f64) != (change[0] < 0);
     } else {
         let y_intercept = start[1] as f64 - slope * start[0] as f64;
-        let line_x = (loc.y as f64 - y_intercept) / slope;
+        let line_x = (loc.y - y_intercept) / slope;
 
-        return (loc.x as f64 > line_x) != invert;
+        return (loc.x > line_x) != invert;
     }
 }
 
@@ -50,95 +63,373 @@ pub fn norm(angle: f64) -> f64 {
 
 // Gets the angle from two points
 pub fn pos_to_angle(v1: &Vertex, v2: &Vertex) -> f64 {
-    let delta_x = (v2.x - v1.x) as f64;
-    let delta_y = (v2.y - v1.y) as f64;
+    let delta_x = v2.x - v1.x;
+    let delta_y = v2.y - v1.y;
 
     return delta_y.atan2(delta_x).to_degrees();
 }
 
+// Checks that a lump is made of whole records of a certain size
+fn check_record_size(name: &str, data: &[u8], record_size: usize) -> Result<(), WadError> {
+    if !data.len().is_multiple_of(record_size) {
+        return Err(WadError::MalformedLump {
+            name: name.to_string(),
+            offset: data.len() - data.len() % record_size,
+            reason: format!(
+                "{} bytes is not a multiple of the {} byte record size",
+                data.len(),
+                record_size
+            ),
+        });
+    }
+    return Ok(());
+}
+
+// Binary maps store a missing sidedef as 0xFFFF
+fn sidedef_from_u16(index: u16) -> Option<u32> {
+    if index == 0xFFFF {
+        return None;
+    }
+    return Some(index as u32);
+}
+
+// Only used once the map has been checked to fit in the vanilla limits
+fn sidedef_to_u16(index: Option<u32>) -> u16 {
+    match index {
+        Some(index) => index as u16,
+        None => 0xFFFF,
+    }
+}
+
+// Checks that an index read out of a lump points at something that exists
+fn check_index(
+    name: &str,
+    offset: usize,
+    index: i64,
+    len: usize,
+    what: &str,
+) -> Result<(), WadError> {
+    if index < 0 || index as usize >= len {
+        return Err(WadError::MalformedLump {
+            name: name.to_string(),
+            offset,
+            reason: format!(
+                "{} index {} is out of range (only {} exist)",
+                what, index, len
+            ),
+        });
+    }
+    return Ok(());
+}
+
+// Names of the lumps which can follow a map marker
+const MAP_LUMP_NAMES: [&str; 16] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP", "BEHAVIOR", "SCRIPTS", "TEXTMAP", "ZNODES", "DIALOGUE", "ENDMAP",
+];
+
+// Lumps glBSP puts after a GL_ marker named after the map
+pub(crate) const GL_LUMP_NAMES: [&str; 5] =
+    ["GL_VERT", "GL_SEGS", "GL_SSECT", "GL_NODES", "GL_PVS"];
+
+// Checks if a lump is one of the lumps that make up a map
+pub(crate) fn is_map_lump(name: &LumpName) -> bool {
+    return MAP_LUMP_NAMES.contains(&name.as_str());
+}
+
+// Everything that can go wrong while reading a WAD
+#[derive(Debug)]
+pub enum WadError {
+    // The file couldn't be opened or read
+    Io(io::Error),
+
+    // The first 4 bytes weren't IWAD or PWAD
+    BadMagic(Vec<u8>),
+
+    // A PWAD was given where the base game's IWAD was needed
+    NotIwad(String),
+
+    // The header or directory goes past the end of the file
+    TruncatedDirectory {
+        offset: usize, // Where the directory (or header) should have been
+        num_of_lumps: usize,
+        file_size: usize,
+    },
+
+    // A directory entry points at data past the end of the file
+    LumpOutOfRange {
+        name: String,
+        offset: usize, // Where in the file the lump claims to start
+        size: usize,
+        file_size: usize,
+    },
+
+    // A lump exists but its contents don't make sense
+    MalformedLump {
+        name: String,
+        offset: usize, // Offset into the lump where the problem is
+        reason: String,
+    },
+
+    // A lump being built can't be stored because it goes over one of the format's limits
+    TooBig {
+        name: String,
+        reason: String,
+    },
+
+    // Something that can be read but can't be written back out
+    Unsupported(String),
+
+    // A lump that's needed isn't in any of the files
+    MissingLump(String),
+}
+
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WadError::Io(err) => write!(f, "couldn't read WAD: {}", err),
+            WadError::BadMagic(magic) => write!(
+                f,
+                "not a WAD file (header starts with {:?} instead of IWAD or PWAD)",
+                String::from_utf8_lossy(magic)
+            ),
+            WadError::NotIwad(path) => write!(f, "{} is a PWAD, not an IWAD", path),
+            WadError::TruncatedDirectory {
+                offset,
+                num_of_lumps,
+                file_size,
+            } => write!(
+                f,
+                "directory of {} lumps at offset {} doesn't fit in a {} byte file",
+                num_of_lumps, offset, file_size
+            ),
+            WadError::LumpOutOfRange {
+                name,
+                offset,
+                size,
+                file_size,
+            } => write!(
+                f,
+                "lump {:?} ({} bytes at offset {}) goes past the end of a {} byte file",
+                name, size, offset, file_size
+            ),
+            WadError::MalformedLump {
+                name,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "malformed lump {:?} at offset {}: {}",
+                name, offset, reason
+            ),
+            WadError::TooBig { name, reason } => {
+                write!(f, "can't build lump {:?}: {}", name, reason)
+            }
+            WadError::Unsupported(reason) => write!(f, "can't write WAD: {}", reason),
+            WadError::MissingLump(name) => write!(f, "couldn't find lump {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for WadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WadError {
+    fn from(err: io::Error) -> WadError {
+        WadError::Io(err)
+    }
+}
+
 // A WAD is the primary way that Doom and it's source ports store data
 pub struct Wad {
     // Header of the WAD file, used for identifying details
     pub wad_id: String, // Identifies the WAD as either an IWAD for the base game or a PWAD for a mod
 
-    pub maps: HashMap<String, BspMap>,
+    // Maps by marker name, each one only gets parsed the first time it's asked for
+    maps: HashMap<LumpName, MapEntry>,
+
+    // Every lump in the file in the same order as the directory
+    lumps: Vec<Lump>,
+}
+
+// The bytes of a whole WAD file, either read into memory or memory mapped
+pub enum WadBytes {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+// Where a map is in the directory and the map itself once it's been parsed
+struct MapEntry {
+    marker: usize,
+
+    // Indexes of the map's lumps, including GL nodes
+    lumps: Vec<usize>,
+
+    parsed: OnceLock<BspMap>,
+}
+
+// The marker pairs that surround a group of lumps of the same kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Namespace {
+    Flats,    // F_START to F_END, or FF_START to FF_END in PWADs
+    Sprites,  // S_START to S_END, or SS_START to SS_END in PWADs
+    Patches,  // P_START to P_END, or PP_START to PP_END in PWADs
+    Textures, // TX_START to TX_END, ZDoom's whole textures that don't need to be put together from patches
 }
 
 // Struct which stores Doom maps
 pub struct BspMap {
+    // Which layout the map's lumps were stored in
+    pub format: MapFormat,
+
+    // Which layout the BSP tree was stored in
+    pub node_format: NodeFormat,
+
     pub things: Vec<Thing>,
-    // Player spawn locations and rotations
-    pub p1_spawn: Vertex,
-    pub p1_rot: i16,
-    pub p2_spawn: Vertex,
-    pub p2_rot: i16,
-    pub p3_spawn: Vertex,
-    pub p3_rot: i16,
-    pub p4_spawn: Vertex,
-    pub p4_rot: i16,
 
     pub linedefs: Vec<LineDef>,
     pub sidedefs: Vec<SideDef>,
     pub vertices: Vec<Vertex>,
+
+    // How many of the vertices belong to the map, the rest were made by splitting segs
+    pub num_map_vertices: usize,
+
     pub segs: Vec<Seg>,
     pub subsectors: Vec<SubSector>,
     pub nodes: Vec<Node>,
     pub sectors: Vec<Sector>,
+
+    // Grid of which linedefs are where, for collision and picking
+    pub blockmap: Option<Blockmap>,
+
+    // Which sectors can't see each other
+    pub reject: Reject,
+
+    // Compiled ACS scripts for Hexen format maps, kept as is
+    pub behavior: Option<Vec<u8>>,
+
+    // The ACS source some editors save next to BEHAVIOR and Strife's conversations,
+    // neither gets read but they're kept so writing the map out doesn't lose them
+    pub scripts: Option<Vec<u8>>,
+    pub dialogue: Option<Vec<u8>>,
+
+    // Anything from a UDMF map that doesn't fit in the structs above
+    pub udmf: Option<UdmfExtras>,
+}
+
+// The different ways a map can be stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapFormat {
+    Doom,  // The original format also used by Heretic and most PWADs
+    Hexen, // Has a BEHAVIOR lump and bigger things and linedefs with arguments for their specials
+    Udmf,  // Text based format in a TEXTMAP lump used by most modern source ports
 }
 
+// The data lumps of a single map, looked up by name instead of by position
+struct MapLumps<'a> {
+    lumps: HashMap<&'a str, &'a [u8]>,
+}
+
+// A lump, texture or flat name with the null padding taken off the end
+// Names are stored upper case since the engine doesn't care about case when looking them up
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LumpName(String);
+
 // Holds onto raw lump data
+#[derive(Clone)]
 pub struct Lump {
-    name: String,
+    name: LumpName,
 
-    // A vector to store the raw data of thy lump
-    data: Vec<u8>,
-}
-// Things are 2d objects like monsters or items
-pub struct Thing {
-    x: i16,
-    y: i16,
-    angle: i16,
-    thing_type: i16,
+    // The name exactly as it was in the directory, so writing the file back out doesn't change it
+    raw_name: [u8; 8],
 
-    // Keeps track of if the thing exists in a particular difficulty
-    // or exists in multiplayer
-    easy: bool,
-    medium: bool,
-    hard: bool,
-    multiplayer: bool,
+    // Where the lump was in the file it came from
+    offset: usize,
 
-    // Is the monster waiting for an ambush later on
-    ambush: bool,
+    // The raw data of thy lump
+    data: LumpData,
 }
 
-// Line as well as flags which activate it
-pub struct LineDef {
-    // Indexes of the vertices on both ends
-    pub start: i16,
-    pub end: i16,
+// Where a lump's bytes are kept
+#[derive(Clone)]
+enum LumpData {
+    // A piece of the file it was loaded from, which all the file's lumps share instead of copying
+    File {
+        bytes: Arc<WadBytes>,
+        start: usize,
+        end: usize,
+    },
+
+    // Bytes that don't come straight out of a file, like decompressed ones from a PK3
+    Owned(Vec<u8>),
+}
 
-    // Flags
-    block_players_and_monsters: bool,
-    block_monsters: bool,
+// Places a player can start from or be teleported to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StartKind {
+    Player(u8), // Players 1 to 4
+    Deathmatch,
+    Teleport,
+}
 
-    pub two_sided: bool, // If the linedef is 2 sided and seperates 2 sectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Start {
+    pub kind: StartKind,
+    pub location: Vertex,
+    pub angle: i16,
 
-    // "Pegs" the texture to a point on the linedef
-    pub upper_unpegged: bool,
-    pub lower_unpegged: bool,
+    // Index of the thing it came from
+    pub thing: usize,
+}
 
-    secret: bool,
-    block_sound: bool,
+// Things are 2d objects like monsters or items
+pub struct Thing {
+    // UDMF positions can have fractions so these aren't rounded until the map is written out
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) angle: i16,
+    pub(crate) thing_type: i16,
+
+    // Which skills and game modes the thing is in and if it's waiting in ambush
+    // Unknown bits are kept so they get written back out the same
+    pub flags: ThingFlags,
+
+    // Which game's meanings the flag bits have
+    pub flag_layout: FlagLayout,
+
+    // These are only used by Hexen format maps and are 0 in Doom ones
+    pub tid: i16, // Thing ID that scripts and specials can refer to
+    pub z: f64,   // Height above the floor
+    pub special: u8,
+    pub args: [u8; 5], // Arguments given to the special
+}
 
-    never_automap: bool,
-    always_automap: bool,
+// Line as well as flags which activate it
+pub struct LineDef {
+    // Indexes of the vertices on both ends, 32 bits since UDMF and extended node maps can have more than
+    // the 65536 a binary map can store
+    pub start: u32,
+    pub end: u32,
 
     pub special_type: i16, // What type of linedef is it
     pub sector_tag: i16,   // What sector is it a part of
 
-    // Indexes of the sidedefs
-    pub front_sidedef: i16,
-    pub back_sidedef: i16,
+    // Blocking, two sided, unpegged and the rest, Hexen uses the upper bits for how the special is activated
+    // Unknown bits are kept so they get written back out the same
+    pub flags: LineDefFlags,
+
+    // Arguments given to the special in Hexen format maps, 0 in Doom ones
+    pub args: [u8; 5],
+
+    // Indexes of the sidedefs, None if the line doesn't have that side
+    pub front_sidedef: Option<u32>,
+    pub back_sidedef: Option<u32>,
 }
 
 // Contains the indexes to the textures used by a linedef
@@ -148,31 +439,33 @@ pub struct SideDef {
     pub y_offset: i16,
 
     // Names of the textures used
-    pub upper_texture: String,
-    pub lower_texture: String,
-    pub middle_texture: String,
+    pub upper_texture: LumpName,
+    pub lower_texture: LumpName,
+    pub middle_texture: LumpName,
 
     // What sector the sidedef faces
-    pub facing_sector: i16,
+    pub facing_sector: u32,
 }
 
 // Might as well make it easy to access the coordinates as x and y
+// Binary maps only have whole numbers but UDMF maps and split vertices from nodes can be fractional
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vertex {
-    pub x: i16,
-    pub y: i16,
+    pub x: f64,
+    pub y: f64,
 }
 
 //  A Seg is a segment of a linedef which is used to build a subsector
 pub struct Seg {
     // Start and end vertices
-    pub start: i16,
-    pub end: i16,
+    pub start: u32,
+    pub end: u32,
 
     // Angle in degrees
     pub angle: f64,
 
-    // What linedef is it a segment of
-    pub linedef_num: i16,
+    // What linedef is it a segment of, -1 for minisegs in GL nodes which aren't part of a linedef
+    pub linedef_num: i32,
 
     // If true it's pointing in the opposite direction of the linedef
     pub direction: bool,
@@ -183,27 +476,27 @@ pub struct Seg {
 
 // A SubSector is a convex part of a sector
 pub struct SubSector {
-    ssec_size: i16, // How many sides are in the subsector
-    first_seg: i16, // Index to first seg, also used to find which sector it's in
+    pub ssec_size: u32, // How many sides are in the subsector
+    pub first_seg: u32, // Index to first seg, also used to find which sector it's in
 }
 
 // A Node is a line which splits the map into 2 smaller nodes
 pub struct Node {
-    start: Vec<i16>,  // Start location of line
-    change: Vec<i16>, // Change in the line coordinates
+    pub(crate) start: Vec<i16>,  // Start location of line
+    pub(crate) change: Vec<i16>, // Change in the line coordinates
 
-    right_box: Vec<i16>, // Bounding box for right branch
-    left_box: Vec<i16>,  // Bounding box for left branch
+    pub(crate) right_box: Vec<i16>, // Bounding box for right branch
+    pub(crate) left_box: Vec<i16>,  // Bounding box for left branch
 
     // If right_is_ssec is true then it gives the index to a subsector
     // If not, then it gives the index to another node
-    right_is_ssec: bool,
-    right_index: i16,
+    pub(crate) right_is_ssec: bool,
+    pub(crate) right_index: u32,
 
     // If left_is_ssec is true then it gives the index to a subsector
     // If not, then it gives the index to another node
-    left_is_ssec: bool,
-    left_index: i16,
+    pub(crate) left_is_ssec: bool,
+    pub(crate) left_index: u32,
 }
 
 // A Sector is an area referenced by a Sidedef
@@ -213,8 +506,8 @@ pub struct Sector {
     pub ceiling_height: i16,
 
     // Names of textures used
-    pub floor_texture: String,
-    pub ceiling_texture: String,
+    pub floor_texture: LumpName,
+    pub ceiling_texture: LumpName,
 
     pub light_level: i16, // How much light is in the sector
 
@@ -225,28 +518,71 @@ pub struct Sector {
 
 impl Wad {
     // Loads the file into a struct
-    pub fn load(path: &str) -> Wad {
-        // Opens the file
-        let file = fs::read(path).expect("File might not exist or some other problem opening it");
+    // The file is read into memory once and every lump points into it instead of having its own copy
+    pub fn load(path: &str) -> Result<Wad, WadError> {
+        return Wad::from_bytes(fs::read(path)?);
+    }
 
-        let wad_id = String::from_utf8(file[0..4].to_vec()).expect("Header not valid");
+    // Loads the file by memory mapping it, so only the parts that get used are actually read
+    // The file shouldn't be changed by anything else while it's loaded
+    pub fn load_mapped(path: &str) -> Result<Wad, WadError> {
+        let file = fs::File::open(path)?;
 
-        if !(wad_id == "IWAD".to_string() || wad_id == "PWAD".to_string()) {
-            panic!("Not a wad file");
+        // Safe as long as nothing truncates the file while it's mapped, which is what the comment above asks for
+        let mapped = unsafe { Mmap::map(&file)? };
+        return Wad::parse(Arc::new(WadBytes::Mapped(mapped)));
+    }
+
+    // Loads a WAD that's already in memory
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Wad, WadError> {
+        return Wad::parse(Arc::new(WadBytes::Owned(bytes)));
+    }
+
+    // Reads the header and directory, maps aren't parsed until they're needed
+    fn parse(bytes: Arc<WadBytes>) -> Result<Wad, WadError> {
+        let file: &[u8] = &bytes;
+
+        // PK3s get turned into a list of lumps the same as a WAD would have
+        if pk3::is_zip(file) {
+            return pk3::read_pk3(bytes.clone());
+        }
+
+        if file.len() < 4 || !(&file[0..4] == b"IWAD" || &file[0..4] == b"PWAD") {
+            return Err(WadError::BadMagic(file[0..file.len().min(4)].to_vec()));
+        }
+        let wad_id = String::from_utf8(file[0..4].to_vec()).unwrap();
+
+        if file.len() < 12 {
+            return Err(WadError::TruncatedDirectory {
+                offset: 4,
+                num_of_lumps: 0,
+                file_size: file.len(),
+            });
         }
 
-        let num_of_lumps = <LittleEndian as ByteOrder>::read_u32(&file[4..8]);
+        let num_of_lumps = <LittleEndian as ByteOrder>::read_u32(&file[4..8]) as usize;
 
         // Points to where the directory which keeps track of lumps is
-        let info_table = <LittleEndian as ByteOrder>::read_u32(&file[8..12]);
+        let info_table = <LittleEndian as ByteOrder>::read_u32(&file[8..12]) as usize;
+
+        // Makes sure the whole directory is actually in the file before reading it
+        let directory_end = num_of_lumps
+            .checked_mul(16)
+            .and_then(|size| size.checked_add(info_table));
+        if directory_end.is_none_or(|end| end > file.len()) {
+            return Err(WadError::TruncatedDirectory {
+                offset: info_table,
+                num_of_lumps,
+                file_size: file.len(),
+            });
+        }
 
         let mut lumps: Vec<Lump> = Vec::new(); // Stores the raw lumps to go over in a list
-        let mut maps: HashMap<String, BspMap> = HashMap::new(); // Stores the game maps
 
         // Appends the lump vector with lumps obtained from the WAD
         for i in 0..num_of_lumps {
             // Location of the start of the directory entry
-            let dir_loc = (info_table + 16 * i).try_into().unwrap();
+            let dir_loc = info_table + 16 * i;
 
             // Where in the directory is the lump
             let lump_pos =
@@ -257,114 +593,936 @@ impl Wad {
                 <LittleEndian as ByteOrder>::read_u32(&file[dir_loc + 4..dir_loc + 8]) as usize;
 
             // The name of the lump
-            let lump_name = String::from_utf8(file[dir_loc + 8..dir_loc + 16].to_vec()).unwrap();
-
-            // The raw bytes of the lump as a vector
-            let raw_bytes = file[lump_pos..lump_pos + lump_size].to_vec();
+            let lump_name = LumpName::from_bytes(&file[dir_loc + 8..dir_loc + 16]);
+            let mut raw_name = [0u8; 8];
+            raw_name.copy_from_slice(&file[dir_loc + 8..dir_loc + 16]);
+
+            if lump_pos
+                .checked_add(lump_size)
+                .is_none_or(|end| end > file.len())
+            {
+                return Err(WadError::LumpOutOfRange {
+                    name: lump_name.to_string(),
+                    offset: lump_pos,
+                    size: lump_size,
+                    file_size: file.len(),
+                });
+            }
 
             lumps.push(Lump {
                 name: lump_name,
-                data: raw_bytes,
+                raw_name,
+                offset: lump_pos,
+                data: LumpData::File {
+                    bytes: bytes.clone(),
+                    start: lump_pos,
+                    end: lump_pos + lump_size,
+                },
             });
         }
 
-        // Goes over map lumps to convert into something usable for a renderer
+        Ok(Wad::from_lumps(&wad_id, lumps))
+    }
+
+    // Makes a WAD out of a list of lumps that came from somewhere else
+    pub(crate) fn from_lumps(wad_id: &str, lumps: Vec<Lump>) -> Wad {
+        let maps = Wad::find_maps(&lumps);
+
+        return Wad {
+            wad_id: wad_id.to_string(),
+            maps,
+            lumps,
+        };
+    }
+
+    // Finds every map marker and which lumps belong to it
+    fn find_maps(lumps: &[Lump]) -> HashMap<LumpName, MapEntry> {
+        let mut maps: HashMap<LumpName, MapEntry> = HashMap::new();
+
         let mut i = 0;
-        let mut lump_name = lumps[0].name.clone();
+        while i < lumps.len() {
+            // A map starts with a marker lump (like E1M1 or MAP01) followed by its data lumps
+            let is_marker = !is_map_lump(&lumps[i].name)
+                && i + 1 < lumps.len()
+                && is_map_lump(&lumps[i + 1].name);
+            if !is_marker {
+                i += 1;
+                continue;
+            }
+
+            // Collects the map lumps until something that isn't part of a map shows up,
+            // so it doesn't matter if some are missing or out of order
+            let mut map_lumps: Vec<usize> = Vec::new();
+            let mut j = i + 1;
+            while j < lumps.len() && is_map_lump(&lumps[j].name) {
+                map_lumps.push(j);
+                j += 1;
+            }
 
-        while &lump_name.as_str() != &"PLAYPAL\0" {
-            // PLAYPAL with a null character happens to be the first non map lump in a WAD file
-            let mut map_lumps: Vec<Vec<u8>> = Vec::new();
-            for j in i..i + 11 {
-                map_lumps.push(lumps[j].data.clone());
+            // GL nodes come in their own marker right after the map, which gets cut to 8 characters
+            let mut gl_marker = format!("GL_{}", lumps[i].name);
+            gl_marker.truncate(8);
+            if j < lumps.len() && lumps[j].name == gl_marker.as_str() {
+                j += 1;
+                while j < lumps.len() && GL_LUMP_NAMES.contains(&lumps[j].name()) {
+                    map_lumps.push(j);
+                    j += 1;
+                }
             }
-            maps.insert(lump_name, BspMap::new(&map_lumps));
-            // maps.push(BspMap::new(&map_lumps));
-            i += 11;
-            lump_name = lumps[i].name.clone();
+
+            maps.insert(
+                lumps[i].name.clone(),
+                MapEntry {
+                    marker: i,
+                    lumps: map_lumps,
+                    parsed: OnceLock::new(),
+                },
+            );
+            i = j;
+        }
+
+        return maps;
+    }
+
+    // Gets a map by its marker name, like E1M1 or MAP01
+    // The map is parsed the first time it's asked for and kept after that
+    pub fn map(&self, name: &str) -> Result<Option<&BspMap>, WadError> {
+        let entry = match self.maps.get(&LumpName::new(name)) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        if let Some(map) = entry.parsed.get() {
+            return Ok(Some(map));
+        }
+
+        // The lumps are looked up by name but their data is still borrowed from the file
+        let mut map_lumps = MapLumps::new();
+        for index in entry.lumps.iter() {
+            map_lumps.insert(self.lumps[*index].name(), self.lumps[*index].data());
+        }
+        let map = BspMap::new(&map_lumps)?;
+
+        return Ok(Some(entry.parsed.get_or_init(|| map)));
+    }
+
+    // Gets a map to change, parsing it first if it hasn't been yet
+    pub fn map_mut(&mut self, name: &str) -> Result<Option<&mut BspMap>, WadError> {
+        if self.map(name)?.is_none() {
+            return Ok(None);
+        }
+
+        let entry = self.maps.get_mut(&LumpName::new(name)).unwrap();
+        return Ok(entry.parsed.get_mut());
+    }
+
+    // Checks if a map has been parsed and is being kept around
+    pub fn is_map_loaded(&self, name: &str) -> bool {
+        match self.maps.get(&LumpName::new(name)) {
+            Some(entry) => entry.parsed.get().is_some(),
+            None => false,
+        }
+    }
+
+    // Names of the maps that have been parsed so far
+    pub fn loaded_maps(&self) -> Vec<&LumpName> {
+        return self
+            .map_names()
+            .into_iter()
+            .filter(|name| self.maps[*name].parsed.get().is_some())
+            .collect();
+    }
+
+    // Throws away a parsed map to free its memory, it gets parsed again if it's asked for later
+    // Returns false if the map wasn't loaded
+    pub fn evict_map(&mut self, name: &str) -> bool {
+        match self.maps.get_mut(&LumpName::new(name)) {
+            Some(entry) => entry.parsed.take().is_some(),
+            None => false,
+        }
+    }
+
+    // Throws away every parsed map
+    pub fn evict_maps(&mut self) {
+        for entry in self.maps.values_mut() {
+            entry.parsed.take();
+        }
+    }
+
+    // The data lumps that belong to a map, not including its marker
+    pub(crate) fn map_lumps(&self, name: &str) -> Vec<&Lump> {
+        match self.maps.get(&LumpName::new(name)) {
+            Some(entry) => entry.lumps.iter().map(|i| &self.lumps[*i]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Checks if the file has a map without parsing it
+    pub fn has_map(&self, name: &str) -> bool {
+        return self.maps.contains_key(&LumpName::new(name));
+    }
+
+    // Names of every map in the file in the order they're in the directory
+    pub fn map_names(&self) -> Vec<&LumpName> {
+        let mut entries: Vec<(&LumpName, &MapEntry)> = self.maps.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.marker);
+
+        return entries.into_iter().map(|(name, _)| name).collect();
+    }
+
+    // Every lump in the order they appear in the directory
+    pub fn lumps(&self) -> &[Lump] {
+        return &self.lumps;
+    }
+
+    // Finds a lump by name, if there's more than one the last one wins like in the engine
+    pub fn lump(&self, name: &str) -> Option<&Lump> {
+        let name = LumpName::new(name);
+        return self.lumps.iter().rev().find(|lump| lump.name == name);
+    }
+
+    // Index of a lump in the directory, again picking the last one with that name
+    pub fn lump_index(&self, name: &str) -> Option<usize> {
+        let name = LumpName::new(name);
+        return self.lumps.iter().rposition(|lump| lump.name == name);
+    }
+
+    // Gets the raw bytes of a lump by name
+    pub fn lump_data(&self, name: &str) -> Option<&[u8]> {
+        return self.lump(name).map(|lump| lump.data());
+    }
+
+    // Gets every lump between a pair of marker lumps, not including the markers themselves
+    pub fn lumps_between(&self, start: &str, end: &str) -> Vec<&Lump> {
+        return self
+            .lumps_between_any(&[start], &[end])
+            .into_iter()
+            .map(|i| &self.lumps[i])
+            .collect();
+    }
+
+    // Gets the lumps in a namespace like the flats between F_START and F_END
+    pub fn namespace(&self, namespace: Namespace) -> Vec<&Lump> {
+        return self
+            .namespace_indexes(namespace)
+            .into_iter()
+            .map(|i| &self.lumps[i])
+            .collect();
+    }
+
+    // Same as namespace but gives where the lumps are in the directory
+    pub fn namespace_indexes(&self, namespace: Namespace) -> Vec<usize> {
+        return self.lumps_between_any(namespace.start_markers(), namespace.end_markers());
+    }
+
+    fn lumps_between_any(&self, starts: &[&str], ends: &[&str]) -> Vec<usize> {
+        let mut found: Vec<usize> = Vec::new();
+        let mut inside = false;
+
+        // A WAD can have the same namespace more than once so it keeps going after the end marker
+        for (i, lump) in self.lumps.iter().enumerate() {
+            if starts.contains(&lump.name()) {
+                inside = true;
+            } else if ends.contains(&lump.name()) {
+                inside = false;
+            } else if inside && lump.size() > 0 {
+                // Empty lumps in a namespace are sub-markers like F1_START, not real data
+                found.push(i);
+            }
+        }
+
+        return found;
+    }
+}
+
+impl Namespace {
+    // Markers that open the namespace
+    pub fn start_markers(&self) -> &'static [&'static str] {
+        match self {
+            Namespace::Flats => &["F_START", "FF_START"],
+            Namespace::Sprites => &["S_START", "SS_START"],
+            Namespace::Patches => &["P_START", "PP_START"],
+            Namespace::Textures => &["TX_START"],
+        }
+    }
+
+    // Markers that close the namespace
+    pub fn end_markers(&self) -> &'static [&'static str] {
+        match self {
+            Namespace::Flats => &["F_END", "FF_END"],
+            Namespace::Sprites => &["S_END", "SS_END"],
+            Namespace::Patches => &["P_END", "PP_END"],
+            Namespace::Textures => &["TX_END"],
+        }
+    }
+}
+
+impl LumpName {
+    // Makes a name from a string, cutting it down to 8 characters like the engine does
+    pub fn new(name: &str) -> LumpName {
+        return LumpName::from_bytes(name.as_bytes());
+    }
+
+    // Reads a name stored as 8 bytes padded with nulls
+    // Anything after the first null is ignored since some tools leave garbage there
+    pub fn from_bytes(bytes: &[u8]) -> LumpName {
+        let name: String = bytes
+            .iter()
+            .take(8)
+            .take_while(|byte| **byte != 0)
+            .map(|byte| byte.to_ascii_uppercase() as char)
+            .collect();
+
+        return LumpName(name);
+    }
+
+    // Converts the name back into its padded 8 byte form
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        for (i, c) in self.0.chars().take(8).enumerate() {
+            bytes[i] = c as u8;
+        }
+
+        return bytes;
+    }
+
+    pub fn as_str(&self) -> &str {
+        return &self.0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.0.is_empty();
+    }
+}
+
+impl fmt::Display for LumpName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<&str> for LumpName {
+    fn from(name: &str) -> LumpName {
+        LumpName::new(name)
+    }
+}
+
+impl PartialEq<str> for LumpName {
+    fn eq(&self, other: &str) -> bool {
+        return self.0.eq_ignore_ascii_case(other);
+    }
+}
+
+impl PartialEq<&str> for LumpName {
+    fn eq(&self, other: &&str) -> bool {
+        return self.0.eq_ignore_ascii_case(other);
+    }
+}
+
+impl Lump {
+    // Makes a lump that has its own copy of its data
+    pub(crate) fn new(name: &str, data: Vec<u8>) -> Lump {
+        let name = LumpName::new(name);
+        return Lump {
+            raw_name: name.to_bytes(),
+            name,
+            offset: 0,
+            data: LumpData::Owned(data),
+        };
+    }
+
+    // Makes a lump out of part of a file that's already been loaded
+    pub(crate) fn from_file(name: &str, bytes: Arc<WadBytes>, start: usize, end: usize) -> Lump {
+        let name = LumpName::new(name);
+        return Lump {
+            raw_name: name.to_bytes(),
+            name,
+            offset: start,
+            data: LumpData::File { bytes, start, end },
+        };
+    }
+
+    // The same lump with a different name
+    pub(crate) fn renamed(mut self, name: &str) -> Lump {
+        self.name = LumpName::new(name);
+        self.raw_name = self.name.to_bytes();
+        return self;
+    }
+
+    // Name of the lump without the padding at the end
+    pub fn name(&self) -> &str {
+        return self.name.as_str();
+    }
+
+    // The raw bytes of the lump
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            LumpData::File { bytes, start, end } => &bytes[*start..*end],
+            LumpData::Owned(data) => data,
+        }
+    }
+
+    // Size of the lump in bytes, markers have a size of 0
+    pub fn size(&self) -> usize {
+        return self.data().len();
+    }
+
+    // Where the lump's data started in the file it was loaded from
+    pub fn offset(&self) -> usize {
+        return self.offset;
+    }
+
+    // The 8 bytes of the name from the directory, including any padding and lower case letters
+    pub fn raw_name(&self) -> [u8; 8] {
+        return self.raw_name;
+    }
+}
+
+impl Deref for WadBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            WadBytes::Owned(bytes) => bytes,
+            WadBytes::Mapped(mapped) => mapped,
         }
+    }
+}
 
-        Wad { wad_id, maps }
+impl<'a> MapLumps<'a> {
+    fn new() -> MapLumps<'a> {
+        MapLumps {
+            lumps: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, name: &'a str, data: &'a [u8]) {
+        self.lumps.insert(name, data);
+    }
+
+    fn has(&self, name: &str) -> bool {
+        return self.lumps.contains_key(name);
+    }
+
+    // Gets a lump's data, treating a missing lump as an empty one
+    fn get(&self, name: &str) -> &'a [u8] {
+        return self.lumps.get(name).copied().unwrap_or(&[]);
     }
 }
 
 impl BspMap {
-    fn new(data: &Vec<Vec<u8>>) -> BspMap {
-        let things: Vec<Thing> = Thing::from_bytes(&data[1]);
-
-        // Just in case there is no spawn
-        let mut p1_spawn = Vertex { x: 0, y: 0 };
-        let mut p1_rot: i16 = 0;
-        let mut p2_spawn = Vertex { x: 0, y: 0 };
-        let mut p2_rot: i16 = 0;
-        let mut p3_spawn = Vertex { x: 0, y: 0 };
-        let mut p3_rot: i16 = 0;
-        let mut p4_spawn = Vertex { x: 0, y: 0 };
-        let mut p4_rot: i16 = 0;
-
-        for thing in things.iter() {
-            if thing.thing_type == 1 {
-                p1_spawn = Vertex {
-                    x: thing.x,
-                    y: thing.y,
-                };
-                p1_rot = thing.angle;
+    fn new(data: &MapLumps) -> Result<BspMap, WadError> {
+        // UDMF maps have a TEXTMAP and Hexen maps are the only binary ones with a BEHAVIOR lump
+        let format = if data.has("TEXTMAP") {
+            MapFormat::Udmf
+        } else if data.has("BEHAVIOR") {
+            MapFormat::Hexen
+        } else {
+            MapFormat::Doom
+        };
+
+        let things: Vec<Thing>;
+        let linedefs: Vec<LineDef>;
+        let sidedefs: Vec<SideDef>;
+        let mut vertices: Vec<Vertex>;
+        let sectors: Vec<Sector>;
+        let mut udmf: Option<UdmfExtras> = None;
+        match format {
+            MapFormat::Doom | MapFormat::Hexen => {
+                if format == MapFormat::Hexen {
+                    things = Thing::from_hexen_bytes(data.get("THINGS"))?;
+                    linedefs = LineDef::from_hexen_bytes(data.get("LINEDEFS"))?;
+                } else {
+                    things = Thing::from_bytes(data.get("THINGS"))?;
+                    linedefs = LineDef::from_bytes(data.get("LINEDEFS"))?;
+                }
+                sidedefs = SideDef::from_bytes(data.get("SIDEDEFS"))?;
+                vertices = Vertex::from_bytes(data.get("VERTEXES"))?;
+                sectors = Sector::from_bytes(data.get("SECTORS"))?;
             }
-            if thing.thing_type == 2 {
-                p2_spawn = Vertex {
-                    x: thing.x,
-                    y: thing.y,
-                };
-                p2_rot = thing.angle;
+            MapFormat::Udmf => {
+                let textmap = udmf::parse_textmap(data.get("TEXTMAP"))?;
+                things = textmap.things;
+                linedefs = textmap.linedefs;
+                sidedefs = textmap.sidedefs;
+                vertices = textmap.vertices;
+                sectors = textmap.sectors;
+                udmf = Some(textmap.extras);
             }
-            if thing.thing_type == 3 {
-                p3_spawn = Vertex {
-                    x: thing.x,
-                    y: thing.y,
-                };
-                p3_rot = thing.angle;
+        }
+
+        // UDMF maps keep their nodes in ZNODES, binary maps can have ZDoom's extended nodes in NODES
+        let nodes_lump = if data.has("ZNODES") {
+            "ZNODES"
+        } else {
+            "NODES"
+        };
+
+        // GL nodes get used over the normal ones when a map has both since their subsectors are closed
+        let mut read_nodes = nodes::read_gl_nodes(
+            data.get("GL_VERT"),
+            data.get("GL_SEGS"),
+            data.get("GL_SSECT"),
+            data.get("GL_NODES"),
+            &vertices,
+            &linedefs,
+        )?;
+        if read_nodes.is_none() {
+            read_nodes =
+                nodes::read_extended_nodes(nodes_lump, data.get(nodes_lump), &vertices, &linedefs)?;
+        }
+        if read_nodes.is_none() {
+            read_nodes = nodes::read_deepbsp_nodes(
+                data.get("SEGS"),
+                data.get("SSECTORS"),
+                data.get("NODES"),
+                &vertices,
+                &linedefs,
+            )?;
+        }
+
+        let num_map_vertices = vertices.len();
+        let mut node_format: NodeFormat;
+        let mut segs: Vec<Seg>;
+        let mut subsectors: Vec<SubSector>;
+        let mut nodes: Vec<Node>;
+        match read_nodes {
+            Some(extended) => {
+                node_format = extended.format;
+                vertices.extend(extended.new_vertices);
+                segs = extended.segs;
+                subsectors = extended.subsectors;
+                nodes = extended.nodes;
             }
-            if thing.thing_type == 4 {
-                p4_spawn = Vertex {
-                    x: thing.x,
-                    y: thing.y,
-                };
-                p4_rot = thing.angle;
+            None => {
+                node_format = NodeFormat::Vanilla;
+                segs = Seg::from_bytes(data.get("SEGS"))?;
+                subsectors = SubSector::from_bytes(data.get("SSECTORS"))?;
+                nodes = Node::from_bytes(data.get("NODES"))?;
             }
         }
 
-        let linedefs: Vec<LineDef> = LineDef::from_bytes(&data[2]);
-        let sidedefs: Vec<SideDef> = SideDef::from_bytes(&data[3]);
-        let vertices: Vec<Vertex> = Vertex::from_bytes(&data[4]);
-        let segs: Vec<Seg> = Seg::from_bytes(&data[5]);
-        let subsectors: Vec<SubSector> = SubSector::from_bytes(&data[6]);
-        let nodes: Vec<Node> = Node::from_bytes(&data[7]);
-        let sectors: Vec<Sector> = Sector::from_bytes(&data[8]);
+        // Freshly made or edited maps might not have been run through a node builder yet
+        if nodes.is_empty() && subsectors.is_empty() && !linedefs.is_empty() {
+            let built = nodebuilder::build_nodes(
+                &linedefs,
+                &sidedefs,
+                &sectors,
+                &vertices,
+                &BuildOptions::default(),
+            );
+            node_format = built.format;
+            vertices.extend(built.new_vertices);
+            segs = built.segs;
+            subsectors = built.subsectors;
+            nodes = built.nodes;
+        }
+        let blockmap: Option<Blockmap> = Blockmap::from_bytes(data.get("BLOCKMAP"))?;
+        let reject = Reject::from_bytes(data.get("REJECT"), sectors.len());
 
-        BspMap {
+        let optional = |name: &str| {
+            if data.has(name) {
+                Some(data.get(name).to_vec())
+            } else {
+                None
+            }
+        };
+        let behavior = optional("BEHAVIOR");
+        let scripts = optional("SCRIPTS");
+        let dialogue = optional("DIALOGUE");
+
+        let map = BspMap {
+            format,
+            node_format,
             things,
-            p1_spawn,
-            p1_rot,
-            p2_spawn,
-            p2_rot,
-            p3_spawn,
-            p3_rot,
-            p4_spawn,
-            p4_rot,
             linedefs,
             sidedefs,
             vertices,
+            num_map_vertices,
             segs,
             subsectors,
             nodes,
             sectors,
+            blockmap,
+            reject,
+            behavior,
+            scripts,
+            dialogue,
+            udmf,
+        };
+        map.check_references()?;
+
+        Ok(map)
+    }
+
+    // Makes sure every index in the map points at something that exists,
+    // so the renderer can index into the vectors without panicking
+    fn check_references(&self) -> Result<(), WadError> {
+        // UDMF linedefs don't have a size so the offset just counts them
+        let linedef_size = match self.format {
+            MapFormat::Doom => 14,
+            MapFormat::Hexen => 16,
+            MapFormat::Udmf => 1,
+        };
+        for (i, linedef) in self.linedefs.iter().enumerate() {
+            let offset = i * linedef_size;
+            check_index(
+                "LINEDEFS",
+                offset,
+                linedef.start as i64,
+                self.vertices.len(),
+                "vertex",
+            )?;
+            check_index(
+                "LINEDEFS",
+                offset,
+                linedef.end as i64,
+                self.vertices.len(),
+                "vertex",
+            )?;
+            // Every line needs a front side, the back side is optional
+            let front_sidedef = match linedef.front_sidedef {
+                Some(front_sidedef) => front_sidedef,
+                None => {
+                    return Err(WadError::MalformedLump {
+                        name: "LINEDEFS".to_string(),
+                        offset,
+                        reason: "linedef doesn't have a front sidedef".to_string(),
+                    })
+                }
+            };
+            check_index(
+                "LINEDEFS",
+                offset,
+                front_sidedef as i64,
+                self.sidedefs.len(),
+                "sidedef",
+            )?;
+            if let Some(back_sidedef) = linedef.back_sidedef {
+                check_index(
+                    "LINEDEFS",
+                    offset,
+                    back_sidedef as i64,
+                    self.sidedefs.len(),
+                    "sidedef",
+                )?;
+            }
+        }
+
+        for (i, sidedef) in self.sidedefs.iter().enumerate() {
+            check_index(
+                "SIDEDEFS",
+                i * 30,
+                sidedef.facing_sector as i64,
+                self.sectors.len(),
+                "sector",
+            )?;
+        }
+
+        // Node lumps come in a few formats with different record sizes so these offsets just count
+        // the records
+        for (i, seg) in self.segs.iter().enumerate() {
+            check_index("SEGS", i, seg.start as i64, self.vertices.len(), "vertex")?;
+            check_index("SEGS", i, seg.end as i64, self.vertices.len(), "vertex")?;
+            // Minisegs from GL nodes are only there to close off subsectors and don't have a linedef
+            if seg.linedef_num != -1 {
+                check_index(
+                    "SEGS",
+                    i,
+                    seg.linedef_num as i64,
+                    self.linedefs.len(),
+                    "linedef",
+                )?;
+            }
+        }
+
+        for (i, ssec) in self.subsectors.iter().enumerate() {
+            let last_seg = ssec.first_seg as u64 + ssec.ssec_size as u64;
+            if last_seg > self.segs.len() as u64 {
+                return Err(WadError::MalformedLump {
+                    name: "SSECTORS".to_string(),
+                    offset: i,
+                    reason: format!(
+                        "segs {} to {} are out of range (only {} exist)",
+                        ssec.first_seg,
+                        last_seg,
+                        self.segs.len()
+                    ),
+                });
+            }
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            for (is_ssec, index) in [
+                (node.right_is_ssec, node.right_index),
+                (node.left_is_ssec, node.left_index),
+            ] {
+                if is_ssec {
+                    check_index("NODES", i, index as i64, self.subsectors.len(), "subsector")?;
+                } else {
+                    // Node builders write children before their parents, so a child that isn't earlier in the
+                    // list would make a loop and traverse_bsp would never finish
+                    check_index("NODES", i, index as i64, i, "child node")?;
+                }
+            }
+        }
+
+        if let Some(blockmap) = &self.blockmap {
+            if let Some(linedef) = blockmap.max_linedef() {
+                if linedef >= self.linedefs.len() {
+                    return Err(WadError::MalformedLump {
+                        name: "BLOCKMAP".to_string(),
+                        offset: 0,
+                        reason: format!(
+                            "linedef index {} is out of range (only {} exist)",
+                            linedef,
+                            self.linedefs.len()
+                        ),
+                    });
+                }
+            }
         }
+
+        return Ok(());
+    }
+
+    // Turns the map back into the lumps that go after its marker, in the usual order
+    // Nodes are always written in the vanilla format so they have to fit in its limits
+    pub fn to_lumps(&self) -> Result<Vec<(LumpName, Vec<u8>)>, WadError> {
+        if self.format == MapFormat::Udmf {
+            return Err(WadError::Unsupported(
+                "UDMF maps can't be written back out yet".to_string(),
+            ));
+        }
+
+        // Minisegs from GL nodes can't be stored in SEGS so those maps get vanilla nodes built for them,
+        // the map itself keeps its GL nodes
+        if self.segs.iter().any(|seg| seg.linedef_num == -1) {
+            let map_vertices = &self.vertices[0..self.num_map_vertices];
+            let built = nodebuilder::build_nodes(
+                &self.linedefs,
+                &self.sidedefs,
+                &self.sectors,
+                map_vertices,
+                &BuildOptions::default(),
+            );
+
+            let mut vertices = map_vertices.to_vec();
+            vertices.extend(built.new_vertices);
+            return self.binary_lumps(&vertices, &built.segs, &built.subsectors, &built.nodes);
+        }
+
+        return self.binary_lumps(&self.vertices, &self.segs, &self.subsectors, &self.nodes);
+    }
+
+    // Writes the binary lumps using a set of nodes that might not be the map's own
+    fn binary_lumps(
+        &self,
+        vertices: &[Vertex],
+        segs: &[Seg],
+        subsectors: &[SubSector],
+        nodes: &[Node],
+    ) -> Result<Vec<(LumpName, Vec<u8>)>, WadError> {
+        // Vanilla indexes are 16 bits and children use the top bit to say they're a subsector
+        let too_big = |name: &str, count: usize, limit: usize| -> Result<(), WadError> {
+            if count > limit {
+                return Err(WadError::TooBig {
+                    name: name.to_string(),
+                    reason: format!("{} records is more than the limit of {}", count, limit),
+                });
+            }
+            return Ok(());
+        };
+        too_big("VERTEXES", vertices.len(), 0x10000)?;
+        too_big("SECTORS", self.sectors.len(), 0x10000)?;
+
+        // 0xFFFF is used for a missing side so it can't be a real sidedef
+        too_big("SIDEDEFS", self.sidedefs.len(), 0xFFFF)?;
+        too_big("SEGS", segs.len(), 0x10000)?;
+        too_big("SSECTORS", subsectors.len(), 0x8000)?;
+        too_big("NODES", nodes.len(), 0x8000)?;
+
+        // Positions get rounded to whole map units, anything past that can't be stored at all
+        let out_of_range = |position: f64| {
+            position.round() < i16::MIN as f64 || position.round() > i16::MAX as f64
+        };
+        for (i, vertex) in vertices.iter().enumerate() {
+            if out_of_range(vertex.x) || out_of_range(vertex.y) {
+                return Err(WadError::TooBig {
+                    name: "VERTEXES".to_string(),
+                    reason: format!(
+                        "vertex {} at ({}, {}) doesn't fit in 16 bits",
+                        i, vertex.x, vertex.y
+                    ),
+                });
+            }
+        }
+        for (i, thing) in self.things.iter().enumerate() {
+            if out_of_range(thing.x) || out_of_range(thing.y) || out_of_range(thing.z) {
+                return Err(WadError::TooBig {
+                    name: "THINGS".to_string(),
+                    reason: format!(
+                        "thing {} at ({}, {}, {}) doesn't fit in 16 bits",
+                        i, thing.x, thing.y, thing.z
+                    ),
+                });
+            }
+        }
+
+        let mut things: Vec<u8> = Vec::new();
+        let mut linedefs: Vec<u8> = Vec::new();
+        for thing in self.things.iter() {
+            if self.format == MapFormat::Hexen {
+                things.extend(thing.to_hexen_bytes());
+            } else {
+                things.extend(thing.to_bytes());
+            }
+        }
+        for linedef in self.linedefs.iter() {
+            if self.format == MapFormat::Hexen {
+                linedefs.extend(linedef.to_hexen_bytes());
+            } else {
+                linedefs.extend(linedef.to_bytes());
+            }
+        }
+
+        let blockmap = match &self.blockmap {
+            Some(blockmap) => blockmap.to_bytes()?,
+            None => Vec::new(),
+        };
+
+        let mut lumps: Vec<(LumpName, Vec<u8>)> = vec![
+            (LumpName::new("THINGS"), things),
+            (LumpName::new("LINEDEFS"), linedefs),
+            (
+                LumpName::new("SIDEDEFS"),
+                self.sidedefs.iter().flat_map(|s| s.to_bytes()).collect(),
+            ),
+            (
+                LumpName::new("VERTEXES"),
+                vertices.iter().flat_map(|v| v.to_bytes()).collect(),
+            ),
+            (
+                LumpName::new("SEGS"),
+                segs.iter().flat_map(|s| s.to_bytes()).collect(),
+            ),
+            (
+                LumpName::new("SSECTORS"),
+                subsectors.iter().flat_map(|s| s.to_bytes()).collect(),
+            ),
+            (
+                LumpName::new("NODES"),
+                nodes.iter().flat_map(|n| n.to_bytes()).collect(),
+            ),
+            (
+                LumpName::new("SECTORS"),
+                self.sectors.iter().flat_map(|s| s.to_bytes()).collect(),
+            ),
+            (LumpName::new("REJECT"), self.reject.to_bytes()),
+            (LumpName::new("BLOCKMAP"), blockmap),
+        ];
+        if let Some(behavior) = &self.behavior {
+            lumps.push((LumpName::new("BEHAVIOR"), behavior.clone()));
+        }
+        if let Some(scripts) = &self.scripts {
+            lumps.push((LumpName::new("SCRIPTS"), scripts.clone()));
+        }
+        if let Some(dialogue) = &self.dialogue {
+            lumps.push((LumpName::new("DIALOGUE"), dialogue.clone()));
+        }
+
+        return Ok(lumps);
+    }
+
+    // Throws away the map's nodes and builds new ones, for after the map has been changed
+    pub fn rebuild_nodes(&mut self, options: &BuildOptions) {
+        self.vertices.truncate(self.num_map_vertices);
+
+        let built = nodebuilder::build_nodes(
+            &self.linedefs,
+            &self.sidedefs,
+            &self.sectors,
+            &self.vertices,
+            options,
+        );
+        self.node_format = built.format;
+        self.vertices.extend(built.new_vertices);
+        self.segs = built.segs;
+        self.subsectors = built.subsectors;
+        self.nodes = built.nodes;
+    }
+
+    // Builds a new BLOCKMAP lump from the map's linedefs
+    pub fn build_blockmap(&self) -> Result<Vec<u8>, WadError> {
+        let blockmap = Blockmap::build(&self.linedefs, &self.vertices[0..self.num_map_vertices])?;
+        return blockmap.to_bytes();
+    }
+
+    // Builds a new REJECT lump, either an empty one or one worked out from line of sight
+    pub fn build_reject(&self, mode: RejectMode) -> Vec<u8> {
+        let reject = Reject::build(
+            self.sectors.len(),
+            &self.linedefs,
+            &self.sidedefs,
+            &self.vertices[0..self.num_map_vertices],
+            mode,
+        );
+        return reject.to_bytes();
+    }
+
+    // Traverses the whole tree starting at the root, which is always the last node
+    // A map with only one subsector doesn't have any nodes at all
+    pub fn traverse_from_root(&self, loc: &Vertex) -> Vec<u32> {
+        if self.nodes.is_empty() {
+            if self.subsectors.is_empty() {
+                return Vec::new();
+            }
+            return vec![0];
+        }
+
+        return self.traverse_bsp(self.nodes.len() - 1, loc);
+    }
+
+    // Every player start, deathmatch start and teleport destination in the order they're in the map
+    pub fn starts(&self) -> Vec<Start> {
+        let mut starts: Vec<Start> = Vec::new();
+
+        for (i, thing) in self.things.iter().enumerate() {
+            let kind = match thing.thing_type {
+                1..=4 => StartKind::Player(thing.thing_type as u8),
+                11 => StartKind::Deathmatch,
+                14 => StartKind::Teleport,
+                _ => continue,
+            };
+
+            starts.push(Start {
+                kind,
+                location: Vertex {
+                    x: thing.x,
+                    y: thing.y,
+                },
+                angle: thing.angle,
+                thing: i,
+            });
+        }
+
+        return starts;
+    }
+
+    // Where a player starts, None if the map doesn't have a start for them
+    // If there's more than one the game uses the last one and the others become voodoo dolls
+    pub fn player_start(&self, player: u8) -> Option<Start> {
+        return self
+            .starts()
+            .into_iter()
+            .rev()
+            .find(|start| start.kind == StartKind::Player(player));
     }
 
     // The cool part of the program the bsp traversal
-    pub fn traverse_bsp(&self, node: usize, loc: &Vertex) -> Vec<i16> {
+    pub fn traverse_bsp(&self, node: usize, loc: &Vertex) -> Vec<u32> {
         // Final list of subsector indexes to read from
-        let mut sorted_ssecs: Vec<i16> = Vec::new();
+        let mut sorted_ssecs: Vec<u32> = Vec::new();
 
         // Just to make life a bit simpler
         let current_node = &self.nodes[node];
@@ -398,8 +1556,23 @@ impl BspMap {
         return sorted_ssecs;
     }
 
+    // The outline of a subsector as the start vertex of each of its segs in order
+    // With GL nodes this is a closed convex polygon, otherwise there can be gaps where minisegs would be
+    pub fn subsector_polygon(&self, ssec: usize) -> Vec<&Vertex> {
+        let mut polygon: Vec<&Vertex> = Vec::new();
+        if let Some(subsector) = self.subsectors.get(ssec) {
+            let first = subsector.first_seg as usize;
+            let last = first + subsector.ssec_size as usize;
+            for seg in &self.segs[first..last] {
+                polygon.push(&self.vertices[seg.start as usize]);
+            }
+        }
+
+        return polygon;
+    }
+
     // Cuts out segs out of player view
-    pub fn cut_nonvis(&self, ssecs: &Vec<i16>, loc: &Vertex, rot: f64) -> Vec<&Seg> {
+    pub fn cut_nonvis(&self, ssecs: &Vec<u32>, loc: &Vertex, rot: f64) -> Vec<&Seg> {
         let mut segs: Vec<&Seg> = Vec::new();
         for i in ssecs.iter() {
             let current_ssec = &self.subsectors[*i as usize];
@@ -409,6 +1582,11 @@ impl BspMap {
                 ..(current_ssec.first_seg + current_ssec.ssec_size) as usize];
 
             for seg in segs_list {
+                // Minisegs aren't walls so there's nothing to draw
+                if seg.linedef_num == -1 {
+                    continue;
+                }
+
                 let start = &self.vertices[seg.start as usize];
                 let end = &self.vertices[seg.end as usize];
 
@@ -448,7 +1626,9 @@ impl BspMap {
 }
 
 impl Thing {
-    fn from_bytes(data: &Vec<u8>) -> Vec<Thing> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<Thing>, WadError> {
+        check_record_size("THINGS", data, 10)?;
+
         let mut things: Vec<Thing> = Vec::new();
         // Adds things to the vector
         for i in 0..(data.len() / 10) {
@@ -468,36 +1648,124 @@ impl Thing {
 
             // Gets the bytes used for the flags
             let int_flags =
-                <LittleEndian as ByteOrder>::read_i16(&data[thing_loc + 8..thing_loc + 10]);
-
-            // Rust doesn't really support bits so I have to use a function I wrote to convert the bytes to booleans
-            let easy = bool_from_i16(int_flags, 0);
-            let medium = bool_from_i16(int_flags, 1);
-            let hard = bool_from_i16(int_flags, 2);
-            let ambush = bool_from_i16(int_flags, 3);
-            let multiplayer = bool_from_i16(int_flags, 4);
+                <LittleEndian as ByteOrder>::read_u16(&data[thing_loc + 8..thing_loc + 10]);
 
             // Finally pushes the data into a Thing object
             things.push(Thing {
-                x,
-                y,
+                x: x as f64,
+                y: y as f64,
+                angle,
+                thing_type,
+                flags: ThingFlags::from_bits_retain(int_flags),
+                flag_layout: FlagLayout::Doom,
+                tid: 0,
+                z: 0.0,
+                special: 0,
+                args: [0; 5],
+            })
+        }
+
+        return Ok(things);
+    }
+
+    // Hexen things are 20 bytes since they have a thing ID, a height, and a special with arguments
+    fn from_hexen_bytes(data: &[u8]) -> Result<Vec<Thing>, WadError> {
+        check_record_size("THINGS", data, 20)?;
+
+        let mut things: Vec<Thing> = Vec::new();
+        for i in 0..(data.len() / 20) {
+            let thing_loc: usize = i * 20;
+
+            let tid = <LittleEndian as ByteOrder>::read_i16(&data[thing_loc..thing_loc + 2]);
+            let x = <LittleEndian as ByteOrder>::read_i16(&data[thing_loc + 2..thing_loc + 4]);
+            let y = <LittleEndian as ByteOrder>::read_i16(&data[thing_loc + 4..thing_loc + 6]);
+            let z = <LittleEndian as ByteOrder>::read_i16(&data[thing_loc + 6..thing_loc + 8]);
+            let angle = <LittleEndian as ByteOrder>::read_i16(&data[thing_loc + 8..thing_loc + 10]);
+            let thing_type =
+                <LittleEndian as ByteOrder>::read_i16(&data[thing_loc + 10..thing_loc + 12]);
+            let int_flags =
+                <LittleEndian as ByteOrder>::read_u16(&data[thing_loc + 12..thing_loc + 14]);
+
+            let special = data[thing_loc + 14];
+            let mut args = [0u8; 5];
+            args.copy_from_slice(&data[thing_loc + 15..thing_loc + 20]);
+
+            // The skill and ambush bits are the same as Doom but Hexen has flags for which game modes
+            // the thing is in instead of one for multiplayer only
+            things.push(Thing {
+                x: x as f64,
+                y: y as f64,
                 angle,
-                easy,
-                hard,
                 thing_type,
-                medium,
-                multiplayer,
-                ambush,
+                flags: ThingFlags::from_bits_retain(int_flags),
+                flag_layout: FlagLayout::Hexen,
+                tid,
+                z: z as f64,
+                special,
+                args,
             })
         }
 
-        return things;
+        return Ok(things);
+    }
+
+    // The 10 byte record for a Doom format THINGS lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 10];
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[0..2], self.x.round() as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[2..4], self.y.round() as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[4..6], self.angle);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[6..8], self.thing_type);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[8..10], self.flags.bits());
+
+        return bytes;
+    }
+
+    // The 20 byte record for a Hexen format THINGS lump
+    pub fn to_hexen_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 20];
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[0..2], self.tid);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[2..4], self.x.round() as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[4..6], self.y.round() as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[6..8], self.z.round() as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[8..10], self.angle);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[10..12], self.thing_type);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[12..14], self.flags.bits());
+        bytes[14] = self.special;
+        bytes[15..20].copy_from_slice(&self.args);
+
+        return bytes;
+    }
+
+    // Checks if the thing gets spawned on a skill level in a game mode
+    pub fn appears_on(&self, skill: Skill, mode: GameMode) -> bool {
+        return self.flags.appears_on(self.flag_layout, skill, mode);
+    }
+
+    // Checks if a monster is deaf and waits until it sees the player
+    pub fn is_ambush(&self) -> bool {
+        match self.flag_layout {
+            FlagLayout::Strife => self.flags.contains(ThingFlags::STRIFE_AMBUSH),
+            _ => self.flags.contains(ThingFlags::AMBUSH),
+        }
+    }
+
+    pub fn thing_type(&self) -> i16 {
+        return self.thing_type;
+    }
+
+    // Name, size and sprite for the thing's type, None if it isn't a Doom or Doom II thing
+    // Other games reuse the same numbers for different things so this only makes sense for Doom maps
+    pub fn info(&self) -> Option<&'static ThingInfo> {
+        return things::thing_info(self.thing_type);
     }
 }
 
 impl LineDef {
     // Gets a vector of LineDefs
-    fn from_bytes(data: &Vec<u8>) -> Vec<LineDef> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<LineDef>, WadError> {
+        check_record_size("LINEDEFS", data, 14)?;
+
         let mut linedefs: Vec<LineDef> = Vec::new();
 
         for i in 0..(data.len() / 14) {
@@ -505,23 +1773,14 @@ impl LineDef {
             let linedef_loc: usize = i * 14;
 
             // Gets indexes of the vertices
-            let start = <LittleEndian as ByteOrder>::read_i16(&data[linedef_loc..linedef_loc + 2]);
-            let end =
-                <LittleEndian as ByteOrder>::read_i16(&data[linedef_loc + 2..linedef_loc + 4]);
+            let start =
+                <LittleEndian as ByteOrder>::read_u16(&data[linedef_loc..linedef_loc + 2]) as u32;
+            let end = <LittleEndian as ByteOrder>::read_u16(&data[linedef_loc + 2..linedef_loc + 4])
+                as u32;
 
-            // Gets the flags to be converted as an int and converts it into booleans
+            // Gets the flags
             let int_flags =
-                <LittleEndian as ByteOrder>::read_i16(&data[linedef_loc + 4..linedef_loc + 6]);
-
-            let block_players_and_monsters = bool_from_i16(int_flags, 0);
-            let block_monsters = bool_from_i16(int_flags, 1);
-            let two_sided = bool_from_i16(int_flags, 2);
-            let upper_unpegged = bool_from_i16(int_flags, 3);
-            let lower_unpegged = bool_from_i16(int_flags, 4);
-            let secret = bool_from_i16(int_flags, 5);
-            let block_sound = bool_from_i16(int_flags, 6);
-            let never_automap = bool_from_i16(int_flags, 7);
-            let always_automap = bool_from_i16(int_flags, 8);
+                <LittleEndian as ByteOrder>::read_u16(&data[linedef_loc + 4..linedef_loc + 6]);
 
             //  What type of linedef is it
             let special_type =
@@ -532,36 +1791,127 @@ impl LineDef {
                 <LittleEndian as ByteOrder>::read_i16(&data[linedef_loc + 8..linedef_loc + 10]);
 
             // Get the indexes of the sidedefs
-            let front_sidedef =
-                <LittleEndian as ByteOrder>::read_i16(&data[linedef_loc + 10..linedef_loc + 12]);
-            let back_sidedef =
-                <LittleEndian as ByteOrder>::read_i16(&data[linedef_loc + 12..linedef_loc + 14]);
+            let front_sidedef = sidedef_from_u16(<LittleEndian as ByteOrder>::read_u16(
+                &data[linedef_loc + 10..linedef_loc + 12],
+            ));
+            let back_sidedef = sidedef_from_u16(<LittleEndian as ByteOrder>::read_u16(
+                &data[linedef_loc + 12..linedef_loc + 14],
+            ));
 
             linedefs.push(LineDef {
                 start,
                 end,
-                block_players_and_monsters,
-                block_monsters,
-                two_sided,
-                upper_unpegged,
-                lower_unpegged,
-                secret,
-                block_sound,
-                never_automap,
-                always_automap,
                 special_type,
                 sector_tag,
+                flags: LineDefFlags::from_bits_retain(int_flags),
+                args: [0; 5],
                 front_sidedef,
                 back_sidedef,
             });
         }
 
-        return linedefs;
+        return Ok(linedefs);
+    }
+
+    // Hexen linedefs are 16 bytes, they swap the tag for a one byte special with 5 arguments
+    fn from_hexen_bytes(data: &[u8]) -> Result<Vec<LineDef>, WadError> {
+        check_record_size("LINEDEFS", data, 16)?;
+
+        let mut linedefs: Vec<LineDef> = Vec::new();
+        for i in 0..(data.len() / 16) {
+            let linedef_loc: usize = i * 16;
+
+            let start =
+                <LittleEndian as ByteOrder>::read_u16(&data[linedef_loc..linedef_loc + 2]) as u32;
+            let end = <LittleEndian as ByteOrder>::read_u16(&data[linedef_loc + 2..linedef_loc + 4])
+                as u32;
+
+            // The first 9 flags are the same as Doom
+            let int_flags =
+                <LittleEndian as ByteOrder>::read_u16(&data[linedef_loc + 4..linedef_loc + 6]);
+
+            let special_type = data[linedef_loc + 6] as i16;
+            let mut args = [0u8; 5];
+            args.copy_from_slice(&data[linedef_loc + 7..linedef_loc + 12]);
+
+            let front_sidedef = sidedef_from_u16(<LittleEndian as ByteOrder>::read_u16(
+                &data[linedef_loc + 12..linedef_loc + 14],
+            ));
+            let back_sidedef = sidedef_from_u16(<LittleEndian as ByteOrder>::read_u16(
+                &data[linedef_loc + 14..linedef_loc + 16],
+            ));
+
+            linedefs.push(LineDef {
+                start,
+                end,
+                special_type,
+                // Hexen doesn't have tags, specials that need one use their first argument
+                sector_tag: 0,
+                flags: LineDefFlags::from_bits_retain(int_flags),
+                args,
+                front_sidedef,
+                back_sidedef,
+            });
+        }
+
+        return Ok(linedefs);
+    }
+
+    // The 14 byte record for a Doom format LINEDEFS lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 14];
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[0..2], self.start as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[2..4], self.end as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[4..6], self.flags.bits());
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[6..8], self.special_type);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[8..10], self.sector_tag);
+        <LittleEndian as ByteOrder>::write_u16(
+            &mut bytes[10..12],
+            sidedef_to_u16(self.front_sidedef),
+        );
+        <LittleEndian as ByteOrder>::write_u16(
+            &mut bytes[12..14],
+            sidedef_to_u16(self.back_sidedef),
+        );
+
+        return bytes;
+    }
+
+    // The 16 byte record for a Hexen format LINEDEFS lump
+    pub fn to_hexen_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 16];
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[0..2], self.start as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[2..4], self.end as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[4..6], self.flags.bits());
+        bytes[6] = self.special_type as u8;
+        bytes[7..12].copy_from_slice(&self.args);
+        <LittleEndian as ByteOrder>::write_u16(
+            &mut bytes[12..14],
+            sidedef_to_u16(self.front_sidedef),
+        );
+        <LittleEndian as ByteOrder>::write_u16(
+            &mut bytes[14..16],
+            sidedef_to_u16(self.back_sidedef),
+        );
+
+        return bytes;
+    }
+
+    // How a Hexen linedef's special gets triggered (crossed, used, shot...), stored in bits 10 to 12
+    pub fn activation(&self) -> u8 {
+        return ((self.flags.bits() >> 10) & 7) as u8;
+    }
+
+    // If a Hexen linedef's special can be triggered more than once
+    pub fn repeatable(&self) -> bool {
+        return self.flags.contains(LineDefFlags::HEXEN_REPEAT_SPECIAL);
     }
 }
 
 impl SideDef {
-    fn from_bytes(data: &Vec<u8>) -> Vec<SideDef> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<SideDef>, WadError> {
+        check_record_size("SIDEDEFS", data, 30)?;
+
         let mut sidedefs: Vec<SideDef> = Vec::new();
 
         for i in 0..(data.len() / 30) {
@@ -575,18 +1925,14 @@ impl SideDef {
                 <LittleEndian as ByteOrder>::read_i16(&data[sidedef_loc + 2..sidedef_loc + 4]);
 
             // Gets the names of the textures used
-            let upper_texture = String::from_utf8(data[sidedef_loc + 4..sidedef_loc + 12].to_vec())
-                .expect("invalid upper texture");
-            let lower_texture =
-                String::from_utf8(data[sidedef_loc + 12..sidedef_loc + 20].to_vec())
-                    .expect("invalid upper texture");
-            let middle_texture =
-                String::from_utf8(data[sidedef_loc + 20..sidedef_loc + 28].to_vec())
-                    .expect("invalid upper texture");
+            let upper_texture = LumpName::from_bytes(&data[sidedef_loc + 4..sidedef_loc + 12]);
+            let lower_texture = LumpName::from_bytes(&data[sidedef_loc + 12..sidedef_loc + 20]);
+            let middle_texture = LumpName::from_bytes(&data[sidedef_loc + 20..sidedef_loc + 28]);
 
             // What sector the sidedef faces
             let facing_sector =
-                <LittleEndian as ByteOrder>::read_i16(&data[sidedef_loc + 28..sidedef_loc + 30]);
+                <LittleEndian as ByteOrder>::read_u16(&data[sidedef_loc + 28..sidedef_loc + 30])
+                    as u32;
 
             sidedefs.push(SideDef {
                 x_offset,
@@ -598,12 +1944,27 @@ impl SideDef {
             });
         }
 
-        return sidedefs;
+        return Ok(sidedefs);
+    }
+
+    // The 30 byte record for a SIDEDEFS lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 30];
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[0..2], self.x_offset);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[2..4], self.y_offset);
+        bytes[4..12].copy_from_slice(&self.upper_texture.to_bytes());
+        bytes[12..20].copy_from_slice(&self.lower_texture.to_bytes());
+        bytes[20..28].copy_from_slice(&self.middle_texture.to_bytes());
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[28..30], self.facing_sector as u16);
+
+        return bytes;
     }
 }
 
 impl Vertex {
-    fn from_bytes(data: &Vec<u8>) -> Vec<Vertex> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<Vertex>, WadError> {
+        check_record_size("VERTEXES", data, 4)?;
+
         let mut vertices: Vec<Vertex> = Vec::new();
 
         for i in 0..(data.len() / 4) {
@@ -612,22 +1973,37 @@ impl Vertex {
             let x = <LittleEndian as ByteOrder>::read_i16(&data[vert_loc..vert_loc + 2]);
             let y = <LittleEndian as ByteOrder>::read_i16(&data[vert_loc + 2..vert_loc + 4]);
 
-            vertices.push(Vertex { x, y });
+            vertices.push(Vertex {
+                x: x as f64,
+                y: y as f64,
+            });
         }
 
-        return vertices;
+        return Ok(vertices);
+    }
+
+    // The 4 byte record for a VERTEXES lump, rounded since vertices from node builders can be fractional
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 4];
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[0..2], self.x.round() as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[2..4], self.y.round() as i16);
+
+        return bytes;
     }
 }
 
 impl Seg {
-    fn from_bytes(data: &Vec<u8>) -> Vec<Seg> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<Seg>, WadError> {
+        check_record_size("SEGS", data, 12)?;
+
         let mut segs: Vec<Seg> = Vec::new();
 
         for i in 0..(data.len() / 12) {
             let seg_loc: usize = i * 12;
 
-            let start = <LittleEndian as ByteOrder>::read_i16(&data[seg_loc..seg_loc + 2]);
-            let end = <LittleEndian as ByteOrder>::read_i16(&data[seg_loc + 2..seg_loc + 4]);
+            // Indexes are unsigned so maps can have more than 32767 of something
+            let start = <LittleEndian as ByteOrder>::read_u16(&data[seg_loc..seg_loc + 2]) as u32;
+            let end = <LittleEndian as ByteOrder>::read_u16(&data[seg_loc + 2..seg_loc + 4]) as u32;
 
             // The 16 bit binary angle which goes from -32768 to 32767
             let bin_angle = <LittleEndian as ByteOrder>::read_i16(&data[seg_loc + 4..seg_loc + 6]);
@@ -636,7 +2012,7 @@ impl Seg {
             let angle = bin_angle as f64 * 45.0 / 8192.0;
 
             let linedef_num =
-                <LittleEndian as ByteOrder>::read_i16(&data[seg_loc + 6..seg_loc + 8]);
+                <LittleEndian as ByteOrder>::read_u16(&data[seg_loc + 6..seg_loc + 8]) as i32;
 
             // Converts direction to a boolean
             let int_direction =
@@ -655,32 +2031,62 @@ impl Seg {
             })
         }
 
-        return segs;
+        return Ok(segs);
+    }
+
+    // The 12 byte record for a vanilla SEGS lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Back to a binary angle, 180 degrees wraps around to -32768
+        let bin_angle = (self.angle * 8192.0 / 45.0).round() as i32 as u16;
+
+        let mut bytes = vec![0u8; 12];
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[0..2], self.start as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[2..4], self.end as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[4..6], bin_angle);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[6..8], self.linedef_num as u16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[8..10], self.direction as i16);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[10..12], self.offset);
+
+        return bytes;
     }
 }
 
 impl SubSector {
-    fn from_bytes(data: &Vec<u8>) -> Vec<SubSector> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<SubSector>, WadError> {
+        check_record_size("SSECTORS", data, 4)?;
+
         let mut subsectors: Vec<SubSector> = Vec::new();
 
         for i in 0..(data.len() / 4) {
             let ssec_loc: usize = i * 4;
 
-            let ssec_size = <LittleEndian as ByteOrder>::read_i16(&data[ssec_loc..ssec_loc + 2]);
+            let ssec_size =
+                <LittleEndian as ByteOrder>::read_u16(&data[ssec_loc..ssec_loc + 2]) as u32;
             let first_seg =
-                <LittleEndian as ByteOrder>::read_i16(&data[ssec_loc + 2..ssec_loc + 4]);
+                <LittleEndian as ByteOrder>::read_u16(&data[ssec_loc + 2..ssec_loc + 4]) as u32;
 
             subsectors.push(SubSector {
                 ssec_size,
                 first_seg,
             })
         }
-        return subsectors;
+        return Ok(subsectors);
+    }
+
+    // The 4 byte record for a vanilla SSECTORS lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 4];
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[0..2], self.ssec_size as u16);
+        <LittleEndian as ByteOrder>::write_u16(&mut bytes[2..4], self.first_seg as u16);
+
+        return bytes;
     }
 }
 
 impl Node {
-    fn from_bytes(data: &Vec<u8>) -> Vec<Node> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<Node>, WadError> {
+        check_record_size("NODES", data, 28)?;
+
         let mut nodes: Vec<Node> = Vec::new();
 
         for i in 0..(data.len() / 28) {
@@ -709,21 +2115,16 @@ impl Node {
                 <LittleEndian as ByteOrder>::read_i16(&data[node_loc + 22..node_loc + 24]),
             ];
 
-            let mut right_index =
-                <LittleEndian as ByteOrder>::read_i16(&data[node_loc + 24..node_loc + 26]);
-            let right_is_ssec = right_index < 0;
-            if right_is_ssec {
-                // Extremely stupid trick but I guess it works
-                right_index = (right_index as i32 + 32768) as i16;
-            }
+            // The top bit says if the child is a subsector and the rest is the index
+            let right_child =
+                <LittleEndian as ByteOrder>::read_u16(&data[node_loc + 24..node_loc + 26]);
+            let right_is_ssec = right_child & 0x8000 != 0;
+            let right_index = (right_child & 0x7FFF) as u32;
 
-            let mut left_index =
-                <LittleEndian as ByteOrder>::read_i16(&data[node_loc + 26..node_loc + 28]);
-            let left_is_ssec = left_index < 0;
-            if left_is_ssec {
-                // Extremely stupid trick but I guess it works
-                left_index = (left_index as i32 + 32768) as i16;
-            }
+            let left_child =
+                <LittleEndian as ByteOrder>::read_u16(&data[node_loc + 26..node_loc + 28]);
+            let left_is_ssec = left_child & 0x8000 != 0;
+            let left_index = (left_child & 0x7FFF) as u32;
 
             nodes.push(Node {
                 start,
@@ -737,12 +2138,33 @@ impl Node {
             })
         }
 
-        return nodes;
+        return Ok(nodes);
+    }
+
+    // The 28 byte record for a vanilla NODES lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words: Vec<i16> = Vec::new();
+        words.extend(&self.start);
+        words.extend(&self.change);
+        words.extend(&self.right_box);
+        words.extend(&self.left_box);
+
+        let right_child = self.right_index as u16 | if self.right_is_ssec { 0x8000 } else { 0 };
+        let left_child = self.left_index as u16 | if self.left_is_ssec { 0x8000 } else { 0 };
+        words.push(right_child as i16);
+        words.push(left_child as i16);
+
+        let mut bytes = vec![0u8; 28];
+        <LittleEndian as ByteOrder>::write_i16_into(&words, &mut bytes);
+
+        return bytes;
     }
 }
 
 impl Sector {
-    fn from_bytes(data: &Vec<u8>) -> Vec<Sector> {
+    fn from_bytes(data: &[u8]) -> Result<Vec<Sector>, WadError> {
+        check_record_size("SECTORS", data, 26)?;
+
         let mut sectors: Vec<Sector> = Vec::new();
         for i in 0..data.len() / 26 {
             let sec_loc: usize = i * 26;
@@ -751,10 +2173,8 @@ impl Sector {
             let ceiling_height =
                 <LittleEndian as ByteOrder>::read_i16(&data[sec_loc + 2..sec_loc + 4]);
 
-            let floor_texture = String::from_utf8(data[sec_loc + 4..sec_loc + 12].to_vec())
-                .expect("invalid floor texture");
-            let ceiling_texture = String::from_utf8(data[sec_loc + 12..sec_loc + 20].to_vec())
-                .expect("invalid floor texture");
+            let floor_texture = LumpName::from_bytes(&data[sec_loc + 4..sec_loc + 12]);
+            let ceiling_texture = LumpName::from_bytes(&data[sec_loc + 12..sec_loc + 20]);
 
             let light_level =
                 <LittleEndian as ByteOrder>::read_i16(&data[sec_loc + 20..sec_loc + 22]);
@@ -774,6 +2194,93 @@ impl Sector {
             })
         }
 
-        return sectors;
+        return Ok(sectors);
+    }
+
+    // The 26 byte record for a SECTORS lump
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 26];
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[0..2], self.floor_height);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[2..4], self.ceiling_height);
+        bytes[4..12].copy_from_slice(&self.floor_texture.to_bytes());
+        bytes[12..20].copy_from_slice(&self.ceiling_texture.to_bytes());
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[20..22], self.light_level);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[22..24], self.special_type);
+        <LittleEndian as ByteOrder>::write_i16(&mut bytes[24..26], self.tag_number);
+
+        return bytes;
+    }
+
+    // Sky ceilings get the sky drawn on them and don't have a top texture on walls next to other sky
+    pub fn has_sky_ceiling(&self) -> bool {
+        return flats::is_sky_flat(&self.ceiling_texture);
+    }
+
+    pub fn has_sky_floor(&self) -> bool {
+        return flats::is_sky_flat(&self.floor_texture);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linedef_indexes_are_unsigned() {
+        let mut data = vec![0u8; 14];
+        <LittleEndian as ByteOrder>::write_u16(&mut data[0..2], 40000);
+        <LittleEndian as ByteOrder>::write_u16(&mut data[2..4], 1);
+        <LittleEndian as ByteOrder>::write_u16(&mut data[10..12], 33000);
+        <LittleEndian as ByteOrder>::write_u16(&mut data[12..14], 0xFFFF);
+
+        let linedefs = LineDef::from_bytes(&data).unwrap();
+        assert_eq!(linedefs[0].start, 40000);
+        assert_eq!(linedefs[0].end, 1);
+        assert_eq!(linedefs[0].front_sidedef, Some(33000));
+        assert_eq!(linedefs[0].back_sidedef, None);
+        assert_eq!(linedefs[0].to_bytes(), data);
+    }
+
+    #[test]
+    fn nodes_that_point_back_at_themselves_are_errors() {
+        use crate::writer::WadWriter;
+
+        let words = |values: &[i16]| -> Vec<u8> {
+            return values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+        };
+
+        // One wall in one subsector, with a node that has itself as its left child
+        let mut sidedef = words(&[0, 0]);
+        for name in ["-", "-", "STARTAN3"] {
+            sidedef.extend(LumpName::new(name).to_bytes());
+        }
+        sidedef.extend(words(&[0]));
+        let mut sector = words(&[0, 128]);
+        sector.extend(LumpName::new("FLOOR4_8").to_bytes());
+        sector.extend(LumpName::new("CEIL3_5").to_bytes());
+        sector.extend(words(&[160, 0, 0]));
+
+        let mut writer = WadWriter::new("PWAD");
+        writer.add_lump("MAP01", Vec::new());
+        writer.add_lump("THINGS", Vec::new());
+        writer.add_lump("LINEDEFS", words(&[0, 1, 1, 0, 0, 0, -1]));
+        writer.add_lump("SIDEDEFS", sidedef);
+        writer.add_lump("VERTEXES", words(&[0, 0, 128, 0]));
+        writer.add_lump("SEGS", words(&[0, 1, 0, 0, 0, 0]));
+        writer.add_lump("SSECTORS", words(&[1, 0]));
+        writer.add_lump(
+            "NODES",
+            words(&[0, 0, 128, 0, 0, 0, 0, 128, 0, 0, 0, 128, -0x8000, 0]),
+        );
+        writer.add_lump("SECTORS", sector);
+
+        let wad = Wad::from_bytes(writer.to_bytes()).unwrap();
+        assert!(matches!(
+            wad.map("MAP01"),
+            Err(WadError::MalformedLump { name, .. }) if name == "NODES"
+        ));
     }
 }
@@ -0,0 +1,191 @@
+use crate::wad::{BspMap, Lump, LumpName, Namespace, Wad, WadError};
+use std::collections::HashMap;
+use std::fs;
+
+// Files at least this big get memory mapped instead of read in, so only the lumps that get used are read
+// Most PWADs are small enough that reading them all at once is faster
+pub const MAP_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+// Loads a file, memory mapping it if it's at least map_size bytes
+pub fn load_wad(path: &str, map_size: u64) -> Result<Wad, WadError> {
+    if fs::metadata(path)?.len() >= map_size {
+        return Wad::load_mapped(path);
+    }
+
+    return Wad::load(path);
+}
+
+// An IWAD with any number of PWADs loaded on top of it
+// Like in the engine, anything in a file loaded later replaces the same thing in the files before it
+pub struct WadStack {
+    wads: Vec<Wad>,
+
+    // Where each WAD was loaded from, in the same order as wads
+    paths: Vec<String>,
+}
+
+impl WadStack {
+    // Starts the stack off with the base game
+    pub fn new(iwad_path: &str) -> Result<WadStack, WadError> {
+        let iwad = load_wad(iwad_path, MAP_FILE_SIZE)?;
+        return WadStack::from_iwad(iwad_path, iwad);
+    }
+
+    // Starts the stack off with an IWAD that's already loaded
+    pub fn from_iwad(iwad_path: &str, iwad: Wad) -> Result<WadStack, WadError> {
+        if iwad.wad_id != "IWAD" {
+            return Err(WadError::NotIwad(iwad_path.to_string()));
+        }
+
+        let mut stack = WadStack {
+            wads: Vec::new(),
+            paths: Vec::new(),
+        };
+        stack.add_wad(iwad_path, iwad);
+
+        return Ok(stack);
+    }
+
+    // Loads a PWAD on top of everything that's already loaded
+    pub fn add_pwad(&mut self, path: &str) -> Result<(), WadError> {
+        let pwad = load_wad(path, MAP_FILE_SIZE)?;
+        self.add_wad(path, pwad);
+
+        return Ok(());
+    }
+
+    // Adds an already loaded WAD on top of the stack
+    pub fn add_wad(&mut self, path: &str, wad: Wad) {
+        self.wads.push(wad);
+        self.paths.push(path.to_string());
+    }
+
+    // The loaded WADs, starting with the IWAD
+    pub fn wads(&self) -> &[Wad] {
+        return &self.wads;
+    }
+
+    // Finds a lump by name in the last file that has it
+    pub fn lump(&self, name: &str) -> Option<&Lump> {
+        return self.wads.iter().rev().find_map(|wad| wad.lump(name));
+    }
+
+    // Gets the raw bytes of a lump from the last file that has it
+    pub fn lump_data(&self, name: &str) -> Option<&[u8]> {
+        return self.lump(name).map(|lump| lump.data());
+    }
+
+    // Path of the file that a lump would be loaded from
+    pub fn lump_source(&self, name: &str) -> Option<&str> {
+        let index = self.wads.iter().rposition(|wad| wad.lump(name).is_some())?;
+        return Some(&self.paths[index]);
+    }
+
+    // Gets a map from the last file that has it
+    // PWADs replace the whole map instead of just the lumps they have
+    pub fn map(&self, name: &str) -> Result<Option<&BspMap>, WadError> {
+        match self.wads.iter().rev().find(|wad| wad.has_map(name)) {
+            Some(wad) => wad.map(name),
+            None => Ok(None),
+        }
+    }
+
+    // Throws away a parsed map from every file so it gets parsed again next time
+    pub fn evict_map(&mut self, name: &str) {
+        for wad in self.wads.iter_mut() {
+            wad.evict_map(name);
+        }
+    }
+
+    // Throws away every parsed map in every file
+    pub fn evict_maps(&mut self) {
+        for wad in self.wads.iter_mut() {
+            wad.evict_maps();
+        }
+    }
+
+    // Path of the file that a map would be loaded from
+    pub fn map_source(&self, name: &str) -> Option<&str> {
+        let index = self.wads.iter().rposition(|wad| wad.has_map(name))?;
+        return Some(&self.paths[index]);
+    }
+
+    // Names of every map in any of the files, sorted
+    pub fn map_names(&self) -> Vec<&LumpName> {
+        let mut names: Vec<&LumpName> = Vec::new();
+        for wad in self.wads.iter() {
+            for name in wad.map_names() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+
+        return names;
+    }
+
+    // Gets every lump in a namespace across all the files
+    // A lump with the same name as an earlier one takes its place, new ones go on the end
+    // which is how Boom and later source ports merge flats and sprites
+    pub fn namespace(&self, namespace: Namespace) -> Vec<&Lump> {
+        return self
+            .namespace_indexes(namespace)
+            .into_iter()
+            .map(|(wad, lump)| self.lump_at(wad, lump))
+            .collect();
+    }
+
+    // Same as namespace but gives which file each lump is in and where it is in that file's directory
+    pub fn namespace_indexes(&self, namespace: Namespace) -> Vec<(usize, usize)> {
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+
+        for (wad_index, wad) in self.wads.iter().enumerate() {
+            for lump_index in wad.namespace_indexes(namespace) {
+                let name = wad.lumps()[lump_index].name();
+                match positions.get(name) {
+                    Some(&i) => merged[i] = (wad_index, lump_index),
+                    None => {
+                        positions.insert(name, merged.len());
+                        merged.push((wad_index, lump_index));
+                    }
+                }
+            }
+        }
+
+        return merged;
+    }
+
+    // Gets a lump by which file it's in and where it is in that file's directory
+    pub fn lump_at(&self, wad: usize, lump: usize) -> &Lump {
+        return &self.wads[wad].lumps()[lump];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::WadWriter;
+
+    #[test]
+    fn mapped_files_have_the_same_lumps() {
+        let mut writer = WadWriter::new("PWAD");
+        writer.add_lump("FIRST", vec![1, 2, 3, 4]);
+        writer.add_lump("SECOND", vec![5; 100]);
+
+        let path = std::env::temp_dir().join(format!("wad_stack_test_{}.wad", std::process::id()));
+        let path = path.to_str().unwrap();
+        writer.save(path).unwrap();
+
+        let read = load_wad(path, u64::MAX).unwrap();
+        let mapped = load_wad(path, 0).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(mapped.lumps().len(), 2);
+        for (read, mapped) in read.lumps().iter().zip(mapped.lumps()) {
+            assert_eq!(read.name(), mapped.name());
+            assert_eq!(read.data(), mapped.data());
+        }
+    }
+}
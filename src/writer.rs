@@ -0,0 +1,298 @@
+use crate::wad::{is_map_lump, BspMap, LumpName, Wad, WadError, GL_LUMP_NAMES};
+use byteorder::ByteOrder;
+use byteorder::LittleEndian;
+use std::fs;
+
+// A lump waiting to be written
+struct WriterLump {
+    // Kept as the raw directory bytes so names come back out exactly how they went in
+    name: [u8; 8],
+    data: Vec<u8>,
+
+    // Where the lump was in the file it was loaded from, if it hasn't been changed since
+    offset: Option<usize>,
+}
+
+// Puts together a WAD file from a list of lumps
+// Lumps are written in directory order right after the header with the directory at the end,
+// which is how IWADs and almost every PWAD are laid out, so an unchanged file comes back out the same
+pub struct WadWriter {
+    pub wad_id: String, // IWAD or PWAD
+    lumps: Vec<WriterLump>,
+}
+
+impl WadWriter {
+    // Starts an empty file
+    pub fn new(wad_id: &str) -> WadWriter {
+        return WadWriter {
+            wad_id: wad_id.to_string(),
+            lumps: Vec::new(),
+        };
+    }
+
+    // Starts with every lump from a loaded WAD, exactly as they were
+    pub fn from_wad(wad: &Wad) -> WadWriter {
+        let mut writer = WadWriter::new(&wad.wad_id);
+        for lump in wad.lumps() {
+            writer.lumps.push(WriterLump {
+                name: lump.raw_name(),
+                data: lump.data().to_vec(),
+                offset: Some(lump.offset()),
+            });
+        }
+
+        return writer;
+    }
+
+    // Names of the lumps in order
+    pub fn lump_names(&self) -> Vec<LumpName> {
+        return self
+            .lumps
+            .iter()
+            .map(|lump| LumpName::from_bytes(&lump.name))
+            .collect();
+    }
+
+    // Adds a lump to the end of the file
+    pub fn add_lump(&mut self, name: &str, data: Vec<u8>) {
+        self.lumps.push(WriterLump {
+            name: LumpName::new(name).to_bytes(),
+            data,
+            offset: None,
+        });
+    }
+
+    // Changes the data of the last lump with a name, returns false if there isn't one
+    pub fn set_lump(&mut self, name: &str, data: Vec<u8>) -> bool {
+        match self.find(name) {
+            Some(index) => {
+                self.lumps[index].data = data;
+                self.lumps[index].offset = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Takes out the last lump with a name, returns false if there isn't one
+    pub fn remove_lump(&mut self, name: &str) -> bool {
+        match self.find(name) {
+            Some(index) => {
+                self.lumps.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Replaces a map's lumps with new ones made from a BspMap, or adds it on the end if it isn't there
+    // Any GL nodes after the map get taken out too since they won't match anymore
+    pub fn set_map(&mut self, name: &str, map: &BspMap) -> Result<(), WadError> {
+        let map_lumps = map.to_lumps()?;
+
+        let marker = match self.find(name) {
+            Some(marker) => marker,
+            None => {
+                self.add_lump(name, Vec::new());
+                self.lumps.len() - 1
+            }
+        };
+
+        // Finds where the old map ends
+        let mut end = marker + 1;
+        while end < self.lumps.len() && is_map_lump(&LumpName::from_bytes(&self.lumps[end].name)) {
+            end += 1;
+        }
+        let mut gl_marker = format!("GL_{}", LumpName::new(name));
+        gl_marker.truncate(8);
+        if end < self.lumps.len()
+            && LumpName::from_bytes(&self.lumps[end].name) == gl_marker.as_str()
+        {
+            end += 1;
+            while end < self.lumps.len()
+                && GL_LUMP_NAMES.contains(&LumpName::from_bytes(&self.lumps[end].name).as_str())
+            {
+                end += 1;
+            }
+        }
+
+        let new_lumps = map_lumps.into_iter().map(|(name, data)| WriterLump {
+            name: name.to_bytes(),
+            data,
+            offset: None,
+        });
+        self.lumps.splice(marker + 1..end, new_lumps);
+
+        return Ok(());
+    }
+
+    // Builds the whole file in memory
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut file: Vec<u8> = Vec::new();
+        file.extend(self.wad_id.as_bytes().iter().take(4));
+        file.resize(12, 0);
+
+        let mut directory: Vec<u8> = Vec::new();
+        for lump in self.lumps.iter() {
+            // Markers don't point at anything so they keep whatever offset they had, as long as it's
+            // still inside the file
+            let offset = match lump.offset {
+                Some(offset) if lump.data.is_empty() && offset <= file.len() => offset,
+                _ => file.len(),
+            };
+            file.extend(&lump.data);
+
+            let mut entry = vec![0u8; 16];
+            <LittleEndian as ByteOrder>::write_u32(&mut entry[0..4], offset as u32);
+            <LittleEndian as ByteOrder>::write_u32(&mut entry[4..8], lump.data.len() as u32);
+            entry[8..16].copy_from_slice(&lump.name);
+            directory.extend(entry);
+        }
+
+        let directory_offset = file.len() as u32;
+        <LittleEndian as ByteOrder>::write_u32(&mut file[4..8], self.lumps.len() as u32);
+        <LittleEndian as ByteOrder>::write_u32(&mut file[8..12], directory_offset);
+        file.extend(directory);
+
+        return file;
+    }
+
+    // Writes the file to disk
+    pub fn save(&self, path: &str) -> Result<(), WadError> {
+        fs::write(path, self.to_bytes())?;
+        return Ok(());
+    }
+
+    // Index of the last lump with a name, since that's the one the engine would use
+    fn find(&self, name: &str) -> Option<usize> {
+        return self
+            .lumps
+            .iter()
+            .rposition(|lump| LumpName::from_bytes(&lump.name) == name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lays out a WAD the usual way, lumps after the header and the directory at the end
+    fn build_wad(wad_id: &[u8; 4], lumps: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut file: Vec<u8> = wad_id.to_vec();
+        file.resize(12, 0);
+
+        let mut directory: Vec<u8> = Vec::new();
+        for (name, data) in lumps.iter() {
+            directory.extend((file.len() as u32).to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend(LumpName::new(name).to_bytes());
+            file.extend(data);
+        }
+
+        let directory_offset = file.len() as u32;
+        file[4..8].copy_from_slice(&(lumps.len() as u32).to_le_bytes());
+        file[8..12].copy_from_slice(&directory_offset.to_le_bytes());
+        file.extend(directory);
+
+        return file;
+    }
+
+    fn words(values: &[i16]) -> Vec<u8> {
+        return values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+    }
+
+    fn name_bytes(name: &str) -> Vec<u8> {
+        return LumpName::new(name).to_bytes().to_vec();
+    }
+
+    // A 128 by 128 square room with a player start in the middle and no nodes
+    fn square_map() -> Vec<(&'static str, Vec<u8>)> {
+        let things = words(&[64, 64, 90, 1, 7]);
+
+        // Going around the room so the front sides face in
+        let mut linedefs: Vec<u8> = Vec::new();
+        for (i, (start, end)) in [(0, 3), (3, 2), (2, 1), (1, 0)].iter().enumerate() {
+            linedefs.extend(words(&[*start, *end, 1, 0, 0, i as i16, -1]));
+        }
+
+        let mut sidedefs: Vec<u8> = Vec::new();
+        for _ in 0..4 {
+            sidedefs.extend(words(&[0, 0]));
+            sidedefs.extend(name_bytes("-"));
+            sidedefs.extend(name_bytes("-"));
+            sidedefs.extend(name_bytes("STARTAN3"));
+            sidedefs.extend(words(&[0]));
+        }
+
+        let vertices = words(&[0, 0, 128, 0, 128, 128, 0, 128]);
+
+        let mut sectors = words(&[0, 128]);
+        sectors.extend(name_bytes("FLOOR4_8"));
+        sectors.extend(name_bytes("CEIL3_5"));
+        sectors.extend(words(&[160, 0, 0]));
+
+        return vec![
+            ("MAP01", Vec::new()),
+            ("THINGS", things),
+            ("LINEDEFS", linedefs),
+            ("SIDEDEFS", sidedefs),
+            ("VERTEXES", vertices),
+            ("SECTORS", sectors),
+        ];
+    }
+
+    #[test]
+    fn unchanged_file_comes_back_the_same() {
+        let mut lumps = vec![("PLAYPAL", vec![7u8; 768]), ("F_START", Vec::new())];
+        lumps.push(("FLAT1", vec![3u8; 4096]));
+        lumps.push(("F_END", Vec::new()));
+        lumps.extend(square_map());
+        lumps.push(("ODDSIZE", vec![1, 2, 3]));
+        let bytes = build_wad(b"PWAD", &lumps);
+
+        let wad = Wad::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(WadWriter::from_wad(&wad).to_bytes(), bytes);
+    }
+
+    #[test]
+    fn set_map_keeps_scripts_and_dialogue() {
+        let mut lumps = square_map();
+        lumps.push(("SCRIPTS", b"script 1 open {}".to_vec()));
+        lumps.push(("DIALOGUE", vec![9u8; 16]));
+        lumps.push(("ENDOOM", vec![0u8; 4000]));
+        let wad = Wad::from_bytes(build_wad(b"PWAD", &lumps)).unwrap();
+
+        let mut writer = WadWriter::from_wad(&wad);
+        writer
+            .set_map("MAP01", wad.map("MAP01").unwrap().unwrap())
+            .unwrap();
+
+        let written = Wad::from_bytes(writer.to_bytes()).unwrap();
+        assert_eq!(written.lump_data("SCRIPTS"), Some(&b"script 1 open {}"[..]));
+        assert_eq!(written.lump_data("DIALOGUE"), Some(&[9u8; 16][..]));
+        assert!(written.lump_data("ENDOOM").is_some());
+        assert_eq!(written.map("MAP01").unwrap().unwrap().linedefs.len(), 4);
+    }
+
+    #[test]
+    fn set_map_builds_vanilla_nodes_for_minisegs() {
+        let mut wad = Wad::from_bytes(build_wad(b"PWAD", &square_map())).unwrap();
+
+        // Pretends the nodes came from GL nodes by turning a seg into a miniseg
+        let map = wad.map_mut("MAP01").unwrap().unwrap();
+        map.segs[0].linedef_num = -1;
+
+        let mut writer = WadWriter::from_wad(&wad);
+        writer
+            .set_map("MAP01", wad.map("MAP01").unwrap().unwrap())
+            .unwrap();
+
+        let written = Wad::from_bytes(writer.to_bytes()).unwrap();
+        let map = written.map("MAP01").unwrap().unwrap();
+        assert!(!map.segs.is_empty());
+        assert!(map.segs.iter().all(|seg| seg.linedef_num != -1));
+    }
+}